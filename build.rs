@@ -0,0 +1,18 @@
+//! Generates the gRPC messages, client and server of
+//! `proto/recommender.proto` into `OUT_DIR`, for `src/grpc/pb.rs` to include.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  #[cfg(feature = "grpc")]
+  {
+    println!("cargo:rerun-if-changed=proto/recommender.proto");
+    println!("cargo:rerun-if-env-changed=PROTOC");
+    let mut config = tonic_prost_build::Config::new();
+    // a protoc on the path or named by PROTOC is preferred over the vendored one
+    if std::env::var_os("PROTOC").is_none() {
+      config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_prost_build::configure()
+      .compile_with_config(config, &["proto/recommender.proto"], &["proto"])?;
+  }
+  Ok(())
+}
@@ -1,44 +1,150 @@
-use std::path::PathBuf;
+use std::{
+  collections::HashSet,
+  hash::Hash,
+  marker::PhantomData,
+  num::NonZeroUsize,
+  path::PathBuf
+};
 
 use arroy::{
   Database as ArroyDatabase,
   Reader,
-  Writer,
-  distances::DotProduct
+  Writer
 };
 use heed::{
   Env,
   EnvOpenOptions,
+  RoTxn
 };
 use rand::{
   SeedableRng,
   rngs::StdRng
 };
+use roaring::RoaringBitmap;
 use thiserror::Error;
 use tracing::{Level, span, debug, trace};
 
 use super::{
+  CandidateFilter,
+  KeyedVector,
+  RecommendRequest,
   Recommender,
   RecommendationList,
+  Subject,
   VectorProvider,
-  error::RecommendError
+  VectorRecommender,
+  error::RecommendError,
+  score::{
+    Metric,
+    ScoreNormalizer,
+    ScoredDistance
+  },
+  spatial::{
+    Distance,
+    NavigableIndex,
+    rank_item_neighbors,
+    rank_neighbors
+  }
 };
 
 pub use arroy::distances;
 
+impl ScoredDistance for distances::Angular {
+  const METRIC: Metric = Metric::Cosine;
+
+  /// arroy reports `(1 - cos) / 2`.
+  fn canonical(distance: f32) -> f32 {
+    1f32 - 2f32 * distance
+  }
+}
+
+impl ScoredDistance for distances::Euclidean {
+  const METRIC: Metric = Metric::Euclidean;
+
+  fn canonical(distance: f32) -> f32 {
+    distance
+  }
+}
+
+impl ScoredDistance for distances::Manhattan {
+  const METRIC: Metric = Metric::Manhattan;
+
+  fn canonical(distance: f32) -> f32 {
+    distance
+  }
+}
+
+impl ScoredDistance for distances::DotProduct {
+  const METRIC: Metric = Metric::Dot;
+
+  /// arroy reports the dot product itself rather than a distance.
+  fn canonical(distance: f32) -> f32 {
+    distance
+  }
+}
+
 pub struct AnnoyRecommender<D> {
   pub db: ArroyDatabase<D>,
-  pub env: Env
+  pub env: Env,
+  /// the number of tree nodes inspected per query. Higher values improve
+  /// recall at the cost of latency. Defaults to `n_trees * n_items`.
+  pub search_k: Option<NonZeroUsize>,
+  /// the number of trees to keep when the index is rebuilt after updates.
+  /// If unset, arroy picks a count based on the data.
+  pub n_trees: Option<usize>,
+  /// seed for the RNG used when the index is rebuilt after updates
+  pub seed: Option<u64>,
+  /// how distances are turned into recommendation scores
+  pub score_normalizer: ScoreNormalizer,
+  /// applied to every request, see
+  /// [`RecommendRequest::min_score`](crate::RecommendRequest::min_score)
+  pub min_score: Option<f32>
 }
 
 impl<D> AnnoyRecommender<D> {
   pub fn new(db: ArroyDatabase<D>, env: Env) -> Self {
-    Self { db, env }
+    Self {
+      db,
+      env,
+      search_k: None,
+      n_trees: None,
+      seed: None,
+      score_normalizer: ScoreNormalizer::default(),
+      min_score: None
+    }
+  }
+
+  pub fn with_search_k(mut self, search_k: Option<NonZeroUsize>) -> Self {
+    self.search_k = search_k;
+    self
+  }
+
+  pub fn with_build_params(mut self, n_trees: Option<usize>, seed: Option<u64>) -> Self {
+    self.n_trees = n_trees;
+    self.seed = seed;
+    self
+  }
+
+  pub fn with_score_normalizer(mut self, score_normalizer: ScoreNormalizer) -> Self {
+    self.score_normalizer = score_normalizer;
+    self
   }
 
-  pub fn builder<P, PathRef>() -> AnnoyRecommenderBuilder<P, PathRef>
+  pub fn with_min_score(mut self, min_score: Option<f32>) -> Self {
+    self.min_score = min_score;
+    self
+  }
+
+  /// Start building a recommender whose index uses the distance `D`, e.g.
+  /// `AnnoyRecommender::<distances::Angular>::builder()`.
+  ///
+  /// Scores are defined for arroy's `Angular`, `Euclidean`, `Manhattan` and
+  /// `DotProduct` distances. arroy 0.3 has no binary quantized distances, so
+  /// those aren't supported until arroy is upgraded.
+  pub fn builder<P, PathRef>() -> AnnoyRecommenderBuilder<P, PathRef, D>
     where P: VectorProvider<u32>,
-          PathRef: AsRef<std::path::Path> {
+          PathRef: AsRef<std::path::Path>,
+          D: arroy::Distance {
     AnnoyRecommenderBuilder::default()
   }
 }
@@ -46,75 +152,192 @@ impl<D> AnnoyRecommender<D> {
 #[derive(Builder)]
 #[builder(name = "AnnoyRecommenderBuilder", pattern="owned", public, build_fn(skip))]
 #[allow(dead_code)]
-pub struct AnnoyRecommenderArguments<P, PathRef>
+pub struct AnnoyRecommenderArguments<P, PathRef, D>
   where P: VectorProvider<u32>,
-        PathRef: AsRef<std::path::Path> {
+        PathRef: AsRef<std::path::Path>,
+        D: arroy::Distance {
+  /// the distance used both to build the trees and to query them
+  #[builder(setter(skip))]
+  distance: PhantomData<D>,
   map_size: usize,
   max_dbs: usize,
   /// the path the DB directory
   path: PathRef,
+  /// the number of trees to build. More trees give better recall but a
+  /// larger index. If unset, arroy picks a count based on the data.
+  #[builder(default)]
+  n_trees: usize,
+  /// the default number of tree nodes inspected per query, see
+  /// [`AnnoyRecommender::search_k`]. Zero or unset uses arroy's default.
+  #[builder(default)]
+  search_k: usize,
+  /// seed for the tree-building RNG. Building the same vectors with the
+  /// same seed and tree count produces the same index.
+  #[builder(default, setter(strip_option))]
+  seed: Option<u64>,
+  /// how distances are turned into scores
+  #[builder(default)]
+  score_normalizer: ScoreNormalizer,
+  /// see [`AnnoyRecommender::min_score`]
+  #[builder(default, setter(strip_option))]
+  min_score: Option<f32>,
   /// instructions for loading vectors into the db. If none is provided,
   /// no vectors will be loaded into the DB
   vector_provider: Option<P>
 }
 
-impl<P, PathRef> AnnoyRecommenderBuilder<P, PathRef>
+impl<P, PathRef, D> AnnoyRecommenderBuilder<P, PathRef, D>
   where P: VectorProvider<u32>,
-        PathRef: AsRef<std::path::Path> {
-  pub fn build(self) -> Result<AnnoyRecommender<DotProduct>, AnnoyRecommenderBuilderError> {
+        PathRef: AsRef<std::path::Path>,
+        D: arroy::Distance {
+  /// Open the LMDB environment at `path` and load or reopen the index.
+  ///
+  /// heed keeps one environment per path, so building again with the same
+  /// path reuses the open environment, and fails if the map size or
+  /// database count differ. The database files are memory mapped: callers
+  /// must not let anything but LMDB modify them while the recommender is
+  /// alive, nor put them on a network filesystem.
+  pub fn build(self) -> Result<AnnoyRecommender<D>, AnnoyRecommenderBuilderError> {
     let span  = span!(Level::DEBUG, "annoy-init");
     let _guard = span.enter();
     debug!("Initializing heed environment");
-    let env = EnvOpenOptions::new()
-      .map_size(self.map_size.unwrap())
-      .max_dbs(self.max_dbs.unwrap() as u32)
-      .open(self.path.unwrap())
-      .map_err(|e| {
+    // SAFETY: heed deduplicates environments per canonical path, so opening
+    // the same path twice in this process hands back the live environment
+    // rather than mapping it again. No unsafe LMDB flags are set, and
+    // keeping the files from being modified outside LMDB is left to the
+    // caller, as documented above.
+    let env = unsafe {
+      EnvOpenOptions::new()
+        .map_size(self.map_size.unwrap())
+        .max_dbs(self.max_dbs.unwrap() as u32)
+        .open(self.path.unwrap())
+    }.map_err(|e| {
         AnnoyRecommenderBuilderError::ValidationError(
           format!("Couldn't open heed environment: {:?}", e)
         )
       })?;
-    // let provider = self.vector_provider.unwrap();
+    let n_trees = self.n_trees.filter(|n_trees| *n_trees > 0);
+    let seed = self.seed.flatten();
     let db = match self.vector_provider.unwrap() {
-      Some(provider) => init_db(&env, provider),
+      Some(provider) => init_db(&env, provider, n_trees, seed),
       None => Self::open_existing_db(&env)
     }.map_err(|e| {
       AnnoyRecommenderBuilderError::ValidationError(
         format!("Couldn't open DB connection: {:?}", e)
       )
     })?;
-    Ok(AnnoyRecommender::new(db, env))
+    let search_k = self.search_k.and_then(NonZeroUsize::new);
+    Ok(AnnoyRecommender::new(db, env)
+      .with_search_k(search_k)
+      .with_build_params(n_trees, seed)
+      .with_score_normalizer(self.score_normalizer.unwrap_or_default())
+      .with_min_score(self.min_score.flatten()))
   }
 
-  fn open_existing_db(env: &Env) -> Result<ArroyDatabase<DotProduct>, InitError> {
+  fn open_existing_db(env: &Env) -> Result<ArroyDatabase<D>, InitError> {
     let rtx = env.read_txn()?;
     let db = env.open_database(&rtx, Some("listing-db"))?
-      .ok_or(InitError::NoDB);
+      .ok_or(InitError::NoDB)?;
+    debug!("Checking the DB was built with the {} distance", D::name());
+    Reader::open(&rtx, 0, db)?;
     let _ = rtx.commit();
-    db
+    Ok(db)
   }
 }
 
-fn init_db<P>(env: &Env, provider: P) -> Result<ArroyDatabase<DotProduct>, InitError>
-  where P: VectorProvider<u32> {
-  debug!("Initializing new heed DB");
+fn init_db<P, D>(
+  env: &Env, provider: P,
+  n_trees: Option<usize>, seed: Option<u64>
+) -> Result<ArroyDatabase<D>, InitError>
+  where P: VectorProvider<u32>,
+        D: arroy::Distance {
+  debug!("Initializing new heed DB with the {} distance", D::name());
   let mut wrtx = env.write_txn()?;
   let db = env.create_database(&mut wrtx, Some("listing-db"))?;
-  let writer = Writer::<DotProduct>::new(db, 0, provider.vector_dimensions() as usize);
-  let n_elements = provider.len();
-  debug!("Loading {} vectors", n_elements);
+  let writer = Writer::<D>::new(db, 0, provider.vector_dimensions() as usize);
+  // Items left over from a previous build may have been indexed under
+  // another distance, so start from an empty index.
+  writer.clear(&mut wrtx)?;
+  debug!("Loading about {} vectors", provider.size_hint().0);
   for (i, (id, vector)) in provider.enumerate()
     .map(|(i, key_vec)| (i, key_vec.into())) {
-    trace!("Inserting vector {}/{} with ID \"{}\"", i, n_elements, id);
+    trace!("Inserting vector {} with ID \"{}\"", i, id);
     writer.add_item(&mut wrtx, id, &vector)?;
   }
   debug!("Committing initialize transaction");
-  let mut rng = StdRng::from_entropy();
-  writer.build(&mut wrtx, &mut rng, None)?;
+  let mut rng = build_rng(seed);
+  writer.build(&mut wrtx, &mut rng, n_trees)?;
   wrtx.commit()?;
   Ok(db)
 }
 
+fn build_rng(seed: Option<u64>) -> StdRng {
+  seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
+}
+
+/// A change to the vectors stored in an [`AnnoyRecommender`].
+pub enum IndexUpdate {
+  /// insert the vector, replacing any vector already stored for the key
+  Upsert(KeyedVector<u32>),
+  /// remove the key from the index
+  Delete(u32)
+}
+
+impl<D> AnnoyRecommender<D>
+  where D: arroy::Distance {
+  /// Insert or replace the vector stored for `key` and rebuild the trees.
+  pub fn upsert(&self, key: u32, vector: Vec<f32>) -> Result<(), RecommendError> {
+    self.update([IndexUpdate::Upsert(KeyedVector::new(key, vector))])
+  }
+
+  /// Remove `key` from the index and rebuild the trees. Deleting a key that
+  /// isn't indexed is not an error.
+  pub fn delete(&self, key: u32) -> Result<(), RecommendError> {
+    self.update([IndexUpdate::Delete(key)])
+  }
+
+  /// Apply a set of updates in a single write transaction, then rebuild the
+  /// trees. Only the trees touched by the changed items are updated; nothing
+  /// is written if any update fails.
+  pub fn update<I>(&self, updates: I) -> Result<(), RecommendError>
+    where I: IntoIterator<Item = IndexUpdate> {
+    let span = span!(Level::DEBUG, "arroy-update");
+    let _guard = span.enter();
+    trace!("Creating write transaction");
+    let mut wrtx = self.env.write_txn()?;
+    let dimensions = Reader::open(&wrtx, 0, self.db)?.dimensions();
+    let writer = Writer::<D>::new(self.db, 0, dimensions);
+    for update in updates {
+      match update {
+        IndexUpdate::Upsert(KeyedVector { key, vector }) => {
+          if vector.len() != dimensions {
+            return Err(RecommendError::DimensionMismatch {
+              expected: dimensions,
+              received: vector.len()
+            })
+          }
+          trace!("Upserting vector with ID \"{}\"", key);
+          writer.add_item(&mut wrtx, key, &vector)?;
+        },
+        IndexUpdate::Delete(key) => {
+          trace!("Deleting vector with ID \"{}\"", key);
+          writer.del_item(&mut wrtx, key)?;
+        }
+      }
+    }
+    debug!("Rebuilding trees");
+    writer.build(&mut wrtx, &mut build_rng(self.seed), self.n_trees)?;
+    wrtx.commit()?;
+    Ok(())
+  }
+
+  /// Rebuild the trees over the vectors already stored, e.g. after changing
+  /// [`AnnoyRecommender::n_trees`].
+  pub fn rebuild(&self) -> Result<(), RecommendError> {
+    self.update([])
+  }
+}
+
 #[derive(Debug, Error)]
 pub enum InitError {
   Arroy(#[from] arroy::Error),
@@ -133,66 +356,159 @@ pub struct DatabaseInitConfig {
   pub vector_dimensions: usize
 }
 
-// impl<D> NavigableIndex for AnnoyRecommender<D>
-//   where D: arroy::Distance {
-//   type Key = ListingId;
-//   type Point = Vec<f32>;
-//   type Neighbors = std::vec::IntoIter<Distance<Self::Key>>;
-
-//   fn get_point(&self, key: &Self::Key) -> Option<Self::Point> {
-//     let rtx = self.env.read_txn()
-//       .inspect_err(|e| error!("couldn't open read transaction: {}", e))
-//       .ok()?;
-//     trace!("creating reader");
-//     let reader = Reader::open(&rtx, 0, self.db)
-//       .inspect_err(|e| error!("couldn't create reader: {}", e))
-//       .ok()?;
-//     trace!("converting id");
-//     let item_id: u32 = (*key).try_into().ok()?;
-//     trace!("locating subject vector");
-//     reader.item_vector(&rtx, item_id)
-//       .inspect_err(|e| error!("couldn't read vector: {}", e))
-//       .unwrap()
-//   }
-
-//   #[allow(unused)]
-//   fn search(&self, subject: &Self::Point, n_items: u16) -> Self::Neighbors {
-//     todo!()
-//     let rtx = self.env.read_txn()
-//       .inspect_err(|e| error!("couldn't open read transaction: {}", e))
-//       .ok()?;
-//     trace!("creating reader");
-//     let reader = Reader::open(&rtx, 0, self.db)
-//       .inspect_err(|e| error!("couldn't create reader: {}", e))
-//       .ok()?;
-//     trace!("locating subject vector");
-//     search_with_reader(
-//       subject.into(), n_items, rtc, reader
-//     )
-//   }
-// }
-
-// #[allow(dead_code)]
-// fn search_with_reader<'a, D>(
-//   subject: &[f32], n_items: u16,
-//   rtx: &'a RoTxn, reader: Reader<'a, D>
-// ) -> impl Iterator<Item = Distance<ListingId>>
-//   where D: arroy::Distance {
-//   // TODO: Error handling
-//   reader.nns_by_vector(
-//     rtx, subject, n_items as usize,
-//     None, None
-//   ).unwrap()
-//    .into_iter()
-//    .map(Distance::from)
-// }
+impl<D> NavigableIndex for AnnoyRecommender<D>
+  where D: arroy::Distance + ScoredDistance {
+  type Key = u32;
+  type Point = Vec<f32>;
+  type Neighbors = Vec<Distance<Self::Key>>;
+
+  fn get_point(&self, key: &Self::Key) -> Result<Option<Self::Point>, RecommendError> {
+    trace!("Creating read transaction");
+    let rtx = self.env.read_txn()?;
+    trace!("Creating reader");
+    let reader = Reader::open(&rtx, 0, self.db)?;
+    trace!("Locating subject vector");
+    Ok(reader.item_vector(&rtx, *key)?)
+  }
+
+  fn search(&self, subject: &Self::Point, n_items: u16) -> Result<Self::Neighbors, RecommendError> {
+    trace!("Creating read transaction");
+    let rtx = self.env.read_txn()?;
+    trace!("Creating reader");
+    let reader = Reader::open(&rtx, 0, self.db)?;
+    search_with_reader(&rtx, &reader, subject, n_items, self.search_k, None)
+  }
+
+  fn score(&self, distance: f32) -> f32 {
+    self.score_normalizer.score::<D>(distance)
+  }
+
+  fn min_score(&self) -> Option<f32> {
+    self.min_score
+  }
+}
+
+fn search_with_reader<'t, D>(
+  rtx: &'t RoTxn, reader: &Reader<'t, D>,
+  subject: &[f32], n_items: u16, search_k: Option<NonZeroUsize>,
+  candidates: Option<&RoaringBitmap>
+) -> Result<Vec<Distance<u32>>, RecommendError>
+  where D: arroy::Distance {
+  debug!("Traversing annoy graph");
+  Ok(reader.nns_by_vector(rtx, subject, n_items as usize, search_k, candidates)?
+    .into_iter()
+    .map(Distance::from)
+    .collect())
+}
 
 impl<D, Key, Rec> Recommender<Key, Rec> for AnnoyRecommender<D>
-  where D: arroy::Distance,
-        Key: TryInto<u32> + PartialEq + std::fmt::Debug + Clone,
-        Rec: From<u32> + PartialEq + PartialEq<Key> {
+  where D: arroy::Distance + ScoredDistance,
+        Key: TryInto<u32> + std::fmt::Debug + Clone,
+        Rec: From<u32> + TryInto<u32> + Clone + PartialEq {
     fn recommend(&self, subject_id: &Key, n_recommendations: u16)
       -> Result<RecommendationList<Rec>, RecommendError> {
+    self.recommend_with_search_k(subject_id, n_recommendations, self.search_k)
+  }
+
+  fn recommend_batch(&self, subject_ids: &[Key], n_recommendations: u16)
+      -> Vec<Result<RecommendationList<Rec>, RecommendError>> {
+    let span = span!(Level::TRACE, "arroy-recommend-batch");
+    let _guard = span.enter();
+    debug!("Traversing annoy graph for {} subjects", subject_ids.len());
+    // Errors aren't cloneable, so if the shared transaction can't be set up
+    // every lookup falls back to reporting its own failure.
+    let one_by_one = || subject_ids.iter()
+      .map(|subject_id| self.recommend(subject_id, n_recommendations))
+      .collect();
+    trace!("Creating shared read transaction");
+    let Ok(rtx) = self.env.read_txn() else {
+      return one_by_one()
+    };
+    trace!("Creating shared reader");
+    let Ok(reader) = Reader::open(&rtx, 0, self.db) else {
+      return one_by_one()
+    };
+    subject_ids.iter()
+      .map(|subject_id| {
+        self.recommend_with_reader(
+          &rtx, &reader, subject_id, n_recommendations, self.search_k, None
+        )
+      })
+      .collect()
+  }
+
+  /// Allow lists are passed to arroy as candidates, other filters and
+  /// exclusion sets fall back to over-fetching.
+  fn recommend_with(&self, request: &RecommendRequest<Key, Rec>)
+      -> Result<RecommendationList<Rec>, RecommendError>
+    where Rec: Eq + Hash {
+    let span = span!(Level::TRACE, "arroy-recommend-with");
+    let _guard = span.enter();
+    self.serve_request(request)
+  }
+}
+
+impl<D, Rec> VectorRecommender<Rec> for AnnoyRecommender<D>
+  where D: arroy::Distance + ScoredDistance,
+        Rec: From<u32> + TryInto<u32> + Clone + PartialEq {
+  fn recommend_by_vector(&self, vector: &[f32], n_recommendations: u16)
+      -> Result<RecommendationList<Rec>, RecommendError> {
+    let span = span!(Level::TRACE, "arroy-recommend-by-vector");
+    let _guard = span.enter();
+    trace!("Creating read transaction");
+    let rtx = self.env.read_txn().map_err(RecommendError::DatabaseError)?;
+    trace!("Creating reader");
+    let reader = Reader::open(&rtx, 0, self.db)?;
+    if vector.len() != reader.dimensions() {
+      return Err(RecommendError::DimensionMismatch {
+        expected: reader.dimensions(),
+        received: vector.len()
+      })
+    }
+    let neighbors = search_with_reader(
+      &rtx, &reader, vector, n_recommendations, self.search_k, None
+    )?;
+    let recs = rank_neighbors(self, None, neighbors);
+    trace!("Returning {} recommendations", recs.0.len());
+    Ok(recs)
+  }
+
+  fn recommend_by_vector_filtered(
+    &self, vector: &[f32], n_recommendations: u16, filter: &CandidateFilter<Rec>
+  ) -> Result<RecommendationList<Rec>, RecommendError>
+    where Rec: Eq + Hash {
+    let span = span!(Level::TRACE, "arroy-recommend-by-vector-filtered");
+    let _guard = span.enter();
+    let request = RecommendRequest::<u32, Rec>::vector(vector, n_recommendations)
+      .with_filter(filter);
+    self.serve_request(&request)
+  }
+}
+
+/// The allowed items as arroy item IDs, minus the excluded ones. Items that
+/// can't be converted can't be in the index either, so they're left out.
+fn candidate_bitmap<Rec>(items: &HashSet<Rec>, exclude: Option<&HashSet<Rec>>) -> RoaringBitmap
+  where Rec: TryInto<u32> + Clone {
+  let to_bitmap = |items: &HashSet<Rec>| items.iter()
+    .filter_map(|item| item.clone().try_into().ok())
+    .collect::<RoaringBitmap>();
+  let allowed = to_bitmap(items);
+  match exclude {
+    Some(exclude) => allowed - to_bitmap(exclude),
+    None => allowed
+  }
+}
+
+impl<D> AnnoyRecommender<D>
+  where D: arroy::Distance + ScoredDistance {
+  /// Like [`Recommender::recommend`], but inspecting `search_k` tree nodes
+  /// instead of the recommender's default.
+  pub fn recommend_with_search_k<Key, Rec>(
+    &self, subject_id: &Key, n_recommendations: u16,
+    search_k: Option<NonZeroUsize>
+  ) -> Result<RecommendationList<Rec>, RecommendError>
+    where Key: TryInto<u32> + std::fmt::Debug + Clone,
+          Rec: From<u32> + PartialEq {
     let span = span!(Level::TRACE, "arroy-recommend");
     debug!("Traversing annoy graph");
     let _guard = span.enter();
@@ -200,19 +516,182 @@ impl<D, Key, Rec> Recommender<Key, Rec> for AnnoyRecommender<D>
     let rtx = self.env.read_txn().map_err(RecommendError::DatabaseError)?;
     trace!("Creating reader");
     let reader = Reader::open(&rtx, 0, self.db)?;
+    self.recommend_with_reader(&rtx, &reader, subject_id, n_recommendations, search_k, None)
+  }
+
+  /// Recommend for `subject_id`, only considering `candidates` if given.
+  /// The subject is never a candidate, so it doesn't take up a slot;
+  /// without candidates one more item is searched for in its place.
+  fn recommend_with_reader<'t, Key, Rec>(
+    &self, rtx: &'t RoTxn, reader: &Reader<'t, D>,
+    subject_id: &Key, n_recommendations: u16,
+    search_k: Option<NonZeroUsize>, mut candidates: Option<RoaringBitmap>
+  ) -> Result<RecommendationList<Rec>, RecommendError>
+    where Key: TryInto<u32> + std::fmt::Debug + Clone,
+          Rec: From<u32> + PartialEq {
     trace!("Converting input Id {:?}", subject_id);
     let converted_id: u32 = subject_id.clone().try_into()
       .map_err(|_| RecommendError::IncompatibleId)?;
     trace!("Locating subject vector");
-    let subject_vector = reader.item_vector(&rtx, converted_id)?
+    let subject_vector = reader.item_vector(rtx, converted_id)?
       .ok_or(RecommendError::NotFound)?;
-    let recs = RecommendationList::new_with_subject(
-      subject_id, reader.nns_by_vector(
-        &rtx, &subject_vector, n_recommendations as usize,
-        None, None
-      )?
-    );
+    let n_search = match candidates.as_mut() {
+      Some(candidates) => {
+        candidates.remove(converted_id);
+        n_recommendations
+      },
+      None => n_recommendations.saturating_add(1)
+    };
+    let neighbors = search_with_reader(
+      rtx, reader, &subject_vector, n_search, search_k, candidates.as_ref()
+    )?;
+    let recs = rank_item_neighbors(self, &converted_id, neighbors, n_recommendations);
     trace!("Returning {} recommendations", recs.0.len());
     Ok(recs)
   }
+
+  /// Serve `request` from a single read transaction. An allow list filter
+  /// is passed to arroy as candidates; other filters and exclusion sets are
+  /// applied by over-fetching.
+  fn serve_request<Key, Rec>(&self, request: &RecommendRequest<Key, Rec>)
+      -> Result<RecommendationList<Rec>, RecommendError>
+    where Key: TryInto<u32> + std::fmt::Debug + Clone,
+          Rec: From<u32> + TryInto<u32> + Clone + Eq + Hash {
+    trace!("Creating read transaction");
+    let rtx = self.env.read_txn().map_err(RecommendError::DatabaseError)?;
+    trace!("Creating reader");
+    let reader = Reader::open(&rtx, 0, self.db)?;
+    if let Subject::Vector(vector) = request.subject {
+      if vector.len() != reader.dimensions() {
+        return Err(RecommendError::DimensionMismatch {
+          expected: reader.dimensions(),
+          received: vector.len()
+        })
+      }
+    }
+    let search_k = request.search.search_k.or(self.search_k);
+    let candidates = match request.filter {
+      Some(CandidateFilter::Allow(items)) => candidate_bitmap(items, request.exclude),
+      _ if request.is_filtered() => {
+        return request.overfetch(|n| {
+          self.search_subject(&rtx, &reader, &request.subject, n, search_k, None)
+        })
+      },
+      _ => {
+        let recs = self.search_subject(
+          &rtx, &reader, &request.subject, request.n_ranked(), search_k, None
+        )?;
+        return Ok(request.finish(recs))
+      }
+    };
+    let recs = self.search_subject(
+      &rtx, &reader, &request.subject, request.n_ranked(), search_k, Some(candidates)
+    )?;
+    Ok(request.finish(recs))
+  }
+
+  /// Rank the top `n_recommendations` for `subject`, only considering
+  /// `candidates` if given.
+  fn search_subject<'t, Key, Rec>(
+    &self, rtx: &'t RoTxn, reader: &Reader<'t, D>,
+    subject: &Subject<Key>, n_recommendations: u16,
+    search_k: Option<NonZeroUsize>, candidates: Option<RoaringBitmap>
+  ) -> Result<RecommendationList<Rec>, RecommendError>
+    where Key: TryInto<u32> + std::fmt::Debug + Clone,
+          Rec: From<u32> + PartialEq {
+    match subject {
+      Subject::Item(subject_id) => self.recommend_with_reader(
+        rtx, reader, *subject_id, n_recommendations, search_k, candidates
+      ),
+      Subject::Vector(vector) => {
+        let neighbors = search_with_reader(
+          rtx, reader, vector, n_recommendations, search_k, candidates.as_ref()
+        )?;
+        Ok(rank_neighbors(self, None, neighbors))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, process, vec};
+
+  use super::*;
+  use super::distances::{Angular, DotProduct, Euclidean, Manhattan};
+
+  struct Vectors(vec::IntoIter<KeyedVector<u32>>);
+
+  impl Iterator for Vectors {
+    type Item = KeyedVector<u32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+      self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+      self.0.size_hint()
+    }
+  }
+
+  impl ExactSizeIterator for Vectors {}
+
+  impl VectorProvider<u32> for Vectors {
+    fn vector_dimensions(&self) -> u16 {
+      2
+    }
+  }
+
+  /// Items 2 to 5 are increasingly far from item 1 under every distance.
+  fn vectors() -> Vectors {
+    Vectors(vec![
+      KeyedVector::new(1, vec![1.0, 0.0]),
+      KeyedVector::new(2, vec![0.9, 0.1]),
+      KeyedVector::new(3, vec![0.5, 0.5]),
+      KeyedVector::new(4, vec![0.0, 1.0]),
+      KeyedVector::new(5, vec![-1.0, -0.2])
+    ].into_iter())
+  }
+
+  fn assert_ranks_nearest_first<D>()
+    where D: arroy::Distance + ScoredDistance {
+    let dir = env::temp_dir()
+      .join(format!("annoy-recommender-{}-{}", D::name(), process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let recommender = AnnoyRecommender::<D>::builder::<Vectors, _>()
+      .map_size(1 << 24)
+      .max_dbs(1)
+      .path(dir.clone())
+      .vector_provider(Some(vectors()))
+      .build()
+      .unwrap();
+    let recs: RecommendationList<u32> = recommender.recommend(&1u32, 5).unwrap();
+    fs::remove_dir_all(&dir).ok();
+    let item_ids: Vec<u32> = recs.0.iter().map(|rec| rec.item_id).collect();
+    assert_eq!(item_ids, vec![2, 3, 4, 5], "ranked by {}", D::name());
+    assert!(
+      recs.0.windows(2).all(|pair| pair[0].score > pair[1].score),
+      "{} scores don't descend: {:?}", D::name(), recs
+    );
+  }
+
+  #[test]
+  fn angular_ranks_nearest_first() {
+    assert_ranks_nearest_first::<Angular>();
+  }
+
+  #[test]
+  fn euclidean_ranks_nearest_first() {
+    assert_ranks_nearest_first::<Euclidean>();
+  }
+
+  #[test]
+  fn manhattan_ranks_nearest_first() {
+    assert_ranks_nearest_first::<Manhattan>();
+  }
+
+  #[test]
+  fn dot_product_ranks_nearest_first() {
+    assert_ranks_nearest_first::<DotProduct>();
+  }
 }
@@ -0,0 +1,288 @@
+use std::{
+  fs::{self, File},
+  io::{BufReader, BufWriter, Write},
+  num::NonZeroUsize,
+  path::Path
+};
+
+use anyhow::{Context, Result, bail};
+use arroy::Reader;
+use serde::{
+  Deserialize,
+  Serialize
+};
+
+use recommender::{
+  AnnoyRecommender,
+  BruteForceRecommender,
+  HnswRecommender,
+  Recommender,
+  VectorProvider,
+  VectorRecommender,
+  annoy_recommender::distances,
+  hnsw_recommender::{HnswDistance, check_dump_layers, dist},
+  mapping::RekeyedRecommender,
+  providers::VectorStream,
+  score::{Metric, ScoredDistance},
+  transform::{
+    TransformPipeline,
+    TransformedProvider,
+    TransformingRecommender,
+    VectorTransform
+  }
+};
+
+/// The file describing how an index directory was built.
+const MANIFEST_FILE: &str = "recommender.json";
+
+/// What the CLI records next to an index, so it can be reopened and
+/// inspected without repeating the build options.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+  pub metric: Metric,
+  pub dimensions: usize,
+  pub n_items: usize,
+  #[serde(flatten)]
+  pub backend: Backend,
+  /// the preprocessing applied to the indexed vectors, and so to query
+  /// vectors
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub transform: Option<TransformPipeline>
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum Backend {
+  Annoy(AnnoyParams),
+  Hnsw(HnswParams)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AnnoyParams {
+  /// the LMDB map size in bytes
+  pub map_size: usize,
+  pub n_trees: Option<usize>,
+  pub seed: Option<u64>,
+  pub search_k: Option<usize>
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HnswParams {
+  pub max_connections: usize,
+  pub n_layers: usize,
+  pub ef_construction: usize,
+  pub ef_search: Option<usize>
+}
+
+impl Manifest {
+  pub fn read(dir: &Path) -> Result<Self> {
+    let path = dir.join(MANIFEST_FILE);
+    let input = File::open(&path)
+      .with_context(|| format!("{} isn't an index built by this tool", dir.display()))?;
+    serde_json::from_reader(BufReader::new(input))
+      .with_context(|| format!("couldn't parse {}", path.display()))
+  }
+
+  pub fn write(&self, dir: &Path) -> Result<()> {
+    let mut out = BufWriter::new(File::create(dir.join(MANIFEST_FILE))?);
+    serde_json::to_writer_pretty(&mut out, self)?;
+    writeln!(out)?;
+    Ok(out.flush()?)
+  }
+}
+
+/// An index opened by the CLI, keyed by `u64` whatever the backend's own
+/// key type.
+pub trait Index: Recommender<u64, u64> + VectorRecommender<u64> + Send + Sync {}
+
+impl<T> Index for T
+  where T: Recommender<u64, u64> + VectorRecommender<u64> + Send + Sync {}
+
+/// Query-time overrides of the search parameters recorded in the manifest.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SearchOverrides {
+  pub search_k: Option<usize>,
+  pub ef_search: Option<usize>
+}
+
+/// Pass `vectors` through `steps`, checking they accept the vectors'
+/// dimensions.
+fn transformed<K>(vectors: VectorStream<K>, steps: Vec<VectorTransform>)
+    -> Result<TransformedProvider<VectorStream<K>>> {
+  let pipeline = TransformPipeline::with_steps(vectors.vector_dimensions(), steps)?;
+  Ok(pipeline.wrap(vectors)?)
+}
+
+/// The pipeline to record in the manifest, if it changes the vectors.
+fn recorded_transform<P>(vectors: &TransformedProvider<P>) -> Option<TransformPipeline> {
+  Some(vectors.pipeline().clone()).filter(|pipeline| !pipeline.is_empty())
+}
+
+/// Build an arroy index into `dir` from `vectors` transformed by `steps`,
+/// writing its manifest.
+pub fn build_annoy(
+  dir: &Path, metric: Metric, params: AnnoyParams,
+  vectors: VectorStream<u32>, steps: Vec<VectorTransform>
+) -> Result<Manifest> {
+  fs::create_dir_all(dir)?;
+  let status = vectors.status();
+  let vectors = transformed(vectors, steps)?;
+  let dimensions = vectors.vector_dimensions() as usize;
+  let transform = recorded_transform(&vectors);
+  let n_items = match metric {
+    Metric::Cosine => build_arroy::<distances::Angular, _>(dir, params, vectors),
+    Metric::Euclidean => build_arroy::<distances::Euclidean, _>(dir, params, vectors),
+    Metric::Manhattan => build_arroy::<distances::Manhattan, _>(dir, params, vectors),
+    Metric::Dot => build_arroy::<distances::DotProduct, _>(dir, params, vectors)
+  }?;
+  status.check()?;
+  let manifest = Manifest { metric, dimensions, n_items, backend: Backend::Annoy(params), transform };
+  manifest.write(dir)?;
+  Ok(manifest)
+}
+
+fn build_arroy<D, P>(dir: &Path, params: AnnoyParams, vectors: P) -> Result<usize>
+  where D: arroy::Distance + ScoredDistance,
+        P: VectorProvider<u32> {
+  let mut builder = AnnoyRecommender::<D>::builder()
+    .map_size(params.map_size)
+    .max_dbs(1)
+    .path(dir)
+    .n_trees(params.n_trees.unwrap_or(0))
+    .search_k(params.search_k.unwrap_or(0))
+    .vector_provider(Some(vectors));
+  if let Some(seed) = params.seed {
+    builder = builder.seed(seed);
+  }
+  let recommender = builder.build()?;
+  let rtx = recommender.env.read_txn()?;
+  let n_items = Reader::<D>::open(&rtx, 0, recommender.db)?.n_items();
+  Ok(n_items as usize)
+}
+
+/// hnsw_rs's dot distance asserts its vectors have unit norm, so it would
+/// panic on the vectors we're given.
+const HNSW_DOT: &str = "the hnsw backend doesn't support the dot metric; \
+  normalize the vectors and use the cosine metric instead";
+
+/// Build an HNSW graph from `vectors` transformed by `steps` and dump it
+/// into `dir`, writing its manifest.
+pub fn build_hnsw(
+  dir: &Path, metric: Metric, params: HnswParams,
+  vectors: VectorStream<usize>, steps: Vec<VectorTransform>
+) -> Result<Manifest> {
+  if metric == Metric::Dot {
+    bail!(HNSW_DOT)
+  }
+  check_dump_layers(params.n_layers)?;
+  let status = vectors.status();
+  let vectors = transformed(vectors, steps)?;
+  let dimensions = vectors.vector_dimensions() as usize;
+  let transform = recorded_transform(&vectors);
+  let n_items = match metric {
+    Metric::Cosine => build_graph(dir, params, dist::DistCosine, vectors),
+    Metric::Euclidean => build_graph(dir, params, dist::DistL2, vectors),
+    Metric::Manhattan => build_graph(dir, params, dist::DistL1, vectors),
+    Metric::Dot => bail!(HNSW_DOT)
+  }?;
+  status.check()?;
+  let manifest = Manifest { metric, dimensions, n_items, backend: Backend::Hnsw(params), transform };
+  manifest.write(dir)?;
+  Ok(manifest)
+}
+
+fn build_graph<D, P>(dir: &Path, params: HnswParams, metric: D, vectors: P) -> Result<usize>
+  where D: HnswDistance<f32> + ScoredDistance + Send + Sync,
+        P: VectorProvider<usize> {
+  let recommender = HnswRecommender::builder()
+    .max_connections(params.max_connections)
+    .n_layers(params.n_layers)
+    .ef_coef(params.ef_construction)
+    .ef_search(params.ef_search.unwrap_or(0))
+    .metric(metric)
+    .vector_provider(vectors)
+    .build()?;
+  recommender.save(dir)?;
+  Ok(recommender.n_items())
+}
+
+/// Reopen the index in `dir`, returning it along with its manifest. The
+/// manifest's counts are refreshed from the index, and query vectors are
+/// transformed like the indexed vectors were.
+pub fn open(dir: &Path, overrides: SearchOverrides) -> Result<(Manifest, Box<dyn Index>)> {
+  let mut manifest = Manifest::read(dir)?;
+  let index: Box<dyn Index> = match manifest.backend {
+    Backend::Annoy(params) => {
+      let search_k = overrides.search_k.or(params.search_k);
+      match manifest.metric {
+        Metric::Cosine => open_arroy::<distances::Angular>(dir, &mut manifest, params, search_k),
+        Metric::Euclidean => open_arroy::<distances::Euclidean>(dir, &mut manifest, params, search_k),
+        Metric::Manhattan => open_arroy::<distances::Manhattan>(dir, &mut manifest, params, search_k),
+        Metric::Dot => open_arroy::<distances::DotProduct>(dir, &mut manifest, params, search_k)
+      }?
+    },
+    Backend::Hnsw(params) => {
+      let ef_search = overrides.ef_search.or(params.ef_search);
+      match manifest.metric {
+        Metric::Cosine => open_graph(dir, &mut manifest, dist::DistCosine, ef_search),
+        Metric::Euclidean => open_graph(dir, &mut manifest, dist::DistL2, ef_search),
+        Metric::Manhattan => open_graph(dir, &mut manifest, dist::DistL1, ef_search),
+        Metric::Dot => bail!(HNSW_DOT)
+      }?
+    }
+  };
+  let index: Box<dyn Index> = match manifest.transform.clone() {
+    Some(pipeline) => Box::new(TransformingRecommender::new(pipeline, index)),
+    None => index
+  };
+  Ok((manifest, index))
+}
+
+fn open_arroy<D>(dir: &Path, manifest: &mut Manifest, params: AnnoyParams, search_k: Option<usize>)
+    -> Result<Box<dyn Index>>
+  where D: arroy::Distance + ScoredDistance {
+  let recommender = AnnoyRecommender::<D>::builder::<VectorStream<u32>, _>()
+    .map_size(params.map_size)
+    .max_dbs(1)
+    .path(dir)
+    .vector_provider(None)
+    .build()?
+    .with_search_k(search_k.and_then(NonZeroUsize::new))
+    .with_build_params(params.n_trees, params.seed);
+  let rtx = recommender.env.read_txn()?;
+  let reader = Reader::<D>::open(&rtx, 0, recommender.db)?;
+  manifest.n_items = reader.n_items() as usize;
+  manifest.dimensions = reader.dimensions();
+  drop(rtx);
+  Ok(Box::new(recommender))
+}
+
+fn open_graph<D>(dir: &Path, manifest: &mut Manifest, metric: D, ef_search: Option<usize>)
+    -> Result<Box<dyn Index>>
+  where D: HnswDistance<f32> + ScoredDistance + Send + Sync + 'static {
+  let mut recommender = HnswRecommender::load(dir, metric)?;
+  if ef_search.is_some() {
+    recommender = recommender.with_ef_search(ef_search);
+  }
+  manifest.n_items = recommender.n_items();
+  manifest.dimensions = recommender.vector_dimensions();
+  Ok(Box::new(RekeyedRecommender::<_, usize>::new(recommender)))
+}
+
+/// Exact search over `vectors`, as ground truth for recall. They're
+/// transformed by the index's `transform` first, if it has one.
+pub fn exact(metric: Metric, vectors: VectorStream<usize>, transform: Option<&TransformPipeline>)
+    -> Result<Box<dyn Index>> {
+  if metric == Metric::Manhattan {
+    bail!("exact search doesn't support the manhattan metric")
+  }
+  let status = vectors.status();
+  let steps = transform.map(|pipeline| pipeline.steps().to_vec()).unwrap_or_default();
+  let vectors = transformed(vectors, steps)?;
+  let recommender = BruteForceRecommender::builder()
+    .metric(metric)
+    .vector_provider(vectors)
+    .build()?;
+  status.check()?;
+  Ok(Box::new(RekeyedRecommender::<_, usize>::new(recommender)))
+}
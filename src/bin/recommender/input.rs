@@ -0,0 +1,94 @@
+use std::{
+  fs::File,
+  io::{self, BufReader, Read},
+  path::Path,
+  str::FromStr
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Arg, ArgAction, ArgMatches, value_parser};
+use serde::de::DeserializeOwned;
+
+use recommender::providers::{
+  DelimitedFormat,
+  VectorStream,
+  delimited,
+  jsonl,
+  npy,
+  vecs
+};
+
+const FORMATS: [&str; 6] = ["csv", "tsv", "jsonl", "fvecs", "bvecs", "npy"];
+
+/// The options describing how a vector file is laid out.
+pub fn format_args() -> [Arg; 4] {
+  [
+    Arg::new("format")
+      .long("format")
+      .value_parser(FORMATS)
+      .help("Vector file format [default: guessed from the extension]"),
+    Arg::new("keys")
+      .long("keys")
+      .value_name("FILE")
+      .help("Key file for npy matrices, with one key per row"),
+    Arg::new("key-column")
+      .long("key-column")
+      .value_parser(value_parser!(usize))
+      .default_value("0")
+      .help("Zero-based key column of CSV and TSV files"),
+    Arg::new("header")
+      .long("header")
+      .action(ArgAction::SetTrue)
+      .help("Skip the first row of CSV and TSV files")
+  ]
+}
+
+/// Stream the vectors of `path`, or of stdin if it's `-`, as laid out by
+/// the [`format_args`] in `matches`.
+pub fn open<K>(path: &str, matches: &ArgMatches) -> Result<VectorStream<K>>
+  where K: FromStr + DeserializeOwned + TryFrom<usize> + 'static {
+  let format = match matches.get_one::<String>("format") {
+    Some(format) => format.as_str(),
+    None => guess_format(path)?
+  };
+  if format == "npy" {
+    let keys = matches.get_one::<String>("keys")
+      .ok_or_else(|| anyhow!("npy matrices need a --keys file"))?;
+    let keys = BufReader::new(File::open(keys).with_context(|| format!("couldn't open {}", keys))?);
+    let stream = match path {
+      "-" => npy::stream(BufReader::new(io::stdin()), keys),
+      path => {
+        let matrix = File::open(path).with_context(|| format!("couldn't open {}", path))?;
+        npy::stream_file(matrix, keys)
+      }
+    };
+    return stream.with_context(|| format!("couldn't read vectors from {}", path))
+  }
+  let input: Box<dyn Read> = match path {
+    "-" => Box::new(io::stdin()),
+    path => Box::new(File::open(path).with_context(|| format!("couldn't open {}", path))?)
+  };
+  let input = BufReader::new(input);
+  let delimited_format = |format: DelimitedFormat| {
+    format.with_key_column(*matches.get_one::<usize>("key-column").expect("has a default"))
+      .with_header(matches.get_flag("header"))
+  };
+  let stream = match format {
+    "csv" => delimited::stream(input, delimited_format(DelimitedFormat::csv())),
+    "tsv" => delimited::stream(input, delimited_format(DelimitedFormat::tsv())),
+    "jsonl" => jsonl::stream(input),
+    "fvecs" => vecs::stream_fvecs(input),
+    "bvecs" => vecs::stream_bvecs(input),
+    format => bail!("unsupported format {}", format)
+  };
+  stream.with_context(|| format!("couldn't read vectors from {}", path))
+}
+
+fn guess_format(path: &str) -> Result<&'static str> {
+  let extension = Path::new(path).extension()
+    .and_then(|extension| extension.to_str())
+    .map(str::to_ascii_lowercase);
+  extension.as_deref()
+    .and_then(|extension| FORMATS.into_iter().find(|format| *format == extension))
+    .ok_or_else(|| anyhow!("can't tell the format of {}, pass --format", path))
+}
@@ -0,0 +1,338 @@
+mod index;
+mod input;
+
+use std::{
+  collections::HashSet,
+  fs::File,
+  io::{self, BufReader, Write},
+  path::{Path, PathBuf}
+};
+#[cfg(feature = "http")]
+use std::{
+  net::SocketAddr,
+  sync::Arc
+};
+
+use anyhow::{Context, Result, bail};
+use clap::{
+  Arg,
+  ArgMatches,
+  Command,
+  builder::{PossibleValuesParser, TypedValueParser},
+  value_parser
+};
+use serde::Serialize;
+
+use recommender::{
+  RecommendRequest,
+  eval::{
+    RankingReport,
+    RecallReport,
+    evaluate_ranking,
+    evaluate_recall,
+    read_held_out
+  },
+  score::Metric,
+  transform::VectorTransform
+};
+
+#[cfg(feature = "http")]
+use recommender::http;
+
+#[cfg(feature = "http")]
+use index::Index;
+use index::{
+  AnnoyParams,
+  HnswParams,
+  SearchOverrides
+};
+
+fn main() -> Result<()> {
+  let matches = cli().get_matches();
+  match matches.subcommand() {
+    Some(("build", matches)) => build(matches),
+    Some(("query", matches)) => query(matches),
+    Some(("inspect", matches)) => inspect(matches),
+    Some(("eval", matches)) => eval(matches),
+    #[cfg(feature = "http")]
+    Some(("serve", matches)) => serve(matches),
+    _ => unreachable!("a subcommand is required")
+  }
+}
+
+fn cli() -> Command {
+  let command = Command::new("recommender")
+    .about("Build, query and evaluate vector recommendation indexes")
+    .subcommand_required(true)
+    .arg_required_else_help(true)
+    .subcommand(Command::new("build")
+      .about("Build an arroy database or an HNSW dump from a vector file")
+      .arg(Arg::new("input")
+        .required(true)
+        .help("Vector file to index, or - to read stdin"))
+      .args(input::format_args())
+      .arg(Arg::new("output")
+        .long("output")
+        .short('o')
+        .required(true)
+        .value_parser(value_parser!(PathBuf))
+        .help("Directory to write the index to"))
+      .arg(Arg::new("backend")
+        .long("backend")
+        .required(true)
+        .value_parser(["annoy", "hnsw"]))
+      .arg(metric_arg())
+      .arg(Arg::new("transform")
+        .long("transform")
+        .value_name("FILE")
+        .value_parser(value_parser!(PathBuf))
+        .help("JSON list of preprocessing steps, applied to the vectors and recorded with the index"))
+      .arg(Arg::new("n-trees")
+        .long("n-trees")
+        .value_parser(value_parser!(usize))
+        .help("Number of arroy trees [default: picked by arroy]"))
+      .arg(Arg::new("seed")
+        .long("seed")
+        .value_parser(value_parser!(u64))
+        .help("Seed for building arroy trees"))
+      .arg(Arg::new("map-size")
+        .long("map-size")
+        .value_parser(value_parser!(usize))
+        .default_value("4294967296")
+        .help("LMDB map size in bytes, bounding the arroy database size"))
+      .arg(Arg::new("max-connections")
+        .long("max-connections")
+        .value_parser(value_parser!(usize))
+        .default_value("16")
+        .help("HNSW connections per node"))
+      .arg(Arg::new("n-layers")
+        .long("n-layers")
+        .value_parser(value_parser!(usize))
+        .default_value("16")
+        .help("Maximum number of HNSW layers; only 16 can be dumped"))
+      .arg(Arg::new("ef-construction")
+        .long("ef-construction")
+        .value_parser(value_parser!(usize))
+        .default_value("200")
+        .help("HNSW search width while building"))
+      .args(search_args().map(|arg| arg.help_heading("Default search parameters"))))
+    .subcommand(Command::new("query")
+      .about("Recommend the nearest items to a key or a vector")
+      .arg(index_arg())
+      .arg(Arg::new("key")
+        .long("key")
+        .value_parser(value_parser!(u64))
+        .conflicts_with("vector")
+        .required_unless_present("vector"))
+      .arg(Arg::new("vector")
+        .long("vector")
+        .value_parser(value_parser!(f32))
+        .value_delimiter(',')
+        .allow_negative_numbers(true)
+        .help("Comma separated query vector"))
+      .arg(Arg::new("n")
+        .short('n')
+        .long("n-items")
+        .value_parser(value_parser!(u16))
+        .default_value("10"))
+      .args(search_args()))
+    .subcommand(Command::new("inspect")
+      .about("Print the dimensions, item count, metric and build parameters of an index")
+      .arg(index_arg()))
+    .subcommand(Command::new("eval")
+      .about("Measure ranking quality on held-out pairs, and recall against exact search")
+      .arg(index_arg())
+      .arg(Arg::new("held-out")
+        .long("held-out")
+        .required(true)
+        .value_parser(value_parser!(PathBuf))
+        .help("File of subject,item pairs held out of the index"))
+      .arg(Arg::new("k")
+        .short('k')
+        .value_parser(value_parser!(u16))
+        .value_delimiter(',')
+        .default_value("1,5,10")
+        .help("Cutoffs to report metrics at"))
+      .arg(Arg::new("catalog-size")
+        .long("catalog-size")
+        .value_parser(value_parser!(usize))
+        .help("Number of recommendable items, for coverage [default: the index size]"))
+      .arg(Arg::new("exact")
+        .long("exact")
+        .value_name("VECTORS")
+        .help("Vector file the index was built from, to measure recall against exact search"))
+      .args(input::format_args())
+      .args(search_args()));
+  #[cfg(feature = "http")]
+  let command = command.subcommand(Command::new("serve")
+    .about("Serve recommendations from an index over HTTP")
+    .arg(index_arg())
+    .arg(Arg::new("listen")
+      .long("listen")
+      .value_parser(value_parser!(SocketAddr))
+      .default_value("127.0.0.1:8080")
+      .help("Address to listen on"))
+    .args(search_args()));
+  command
+}
+
+fn index_arg() -> Arg {
+  Arg::new("index")
+    .required(true)
+    .value_parser(value_parser!(PathBuf))
+    .help("Index directory written by the build command")
+}
+
+fn metric_arg() -> Arg {
+  Arg::new("metric")
+    .long("metric")
+    .default_value("cosine")
+    .value_parser(PossibleValuesParser::new(["cosine", "euclidean", "manhattan", "dot"])
+      .map(|metric| match metric.as_str() {
+        "euclidean" => Metric::Euclidean,
+        "manhattan" => Metric::Manhattan,
+        "dot" => Metric::Dot,
+        _ => Metric::Cosine
+      }))
+}
+
+fn search_args() -> [Arg; 2] {
+  [
+    Arg::new("search-k")
+      .long("search-k")
+      .value_parser(value_parser!(usize))
+      .help("Tree nodes arroy inspects per query"),
+    Arg::new("ef-search")
+      .long("ef-search")
+      .value_parser(value_parser!(usize))
+      .help("HNSW search width per query")
+  ]
+}
+
+fn search_overrides(matches: &ArgMatches) -> SearchOverrides {
+  SearchOverrides {
+    search_k: matches.get_one("search-k").copied(),
+    ef_search: matches.get_one("ef-search").copied()
+  }
+}
+
+fn build(matches: &ArgMatches) -> Result<()> {
+  let input = matches.get_one::<String>("input").expect("required");
+  let output = matches.get_one::<PathBuf>("output").expect("required");
+  let metric = *matches.get_one::<Metric>("metric").expect("has a default");
+  let overrides = search_overrides(matches);
+  let steps: Vec<VectorTransform> = match matches.get_one::<PathBuf>("transform") {
+    Some(path) => serde_json::from_reader(open_file(path)?)
+      .with_context(|| format!("couldn't parse {}", path.display()))?,
+    None => Vec::new()
+  };
+  let manifest = match matches.get_one::<String>("backend").expect("required").as_str() {
+    "annoy" => {
+      let params = AnnoyParams {
+        map_size: *matches.get_one("map-size").expect("has a default"),
+        n_trees: matches.get_one("n-trees").copied(),
+        seed: matches.get_one("seed").copied(),
+        search_k: overrides.search_k
+      };
+      index::build_annoy(output, metric, params, input::open(input, matches)?, steps)?
+    },
+    _ => {
+      let params = HnswParams {
+        max_connections: *matches.get_one("max-connections").expect("has a default"),
+        n_layers: *matches.get_one("n-layers").expect("has a default"),
+        ef_construction: *matches.get_one("ef-construction").expect("has a default"),
+        ef_search: overrides.ef_search
+      };
+      index::build_hnsw(output, metric, params, input::open(input, matches)?, steps)?
+    }
+  };
+  print_json(&manifest)
+}
+
+fn query(matches: &ArgMatches) -> Result<()> {
+  let dir = matches.get_one::<PathBuf>("index").expect("required");
+  let n_items = *matches.get_one::<u16>("n").expect("has a default");
+  let (_, index) = index::open(dir, search_overrides(matches))?;
+  let recommendations = match matches.get_one::<u64>("key") {
+    Some(key) => index.recommend_with(&RecommendRequest::item(key, n_items)),
+    None => {
+      let vector: Vec<f32> = matches.get_many("vector").expect("key or vector").copied().collect();
+      index.recommend_by_vector(&vector, n_items)
+    }
+  }?;
+  print_json(&recommendations)
+}
+
+fn inspect(matches: &ArgMatches) -> Result<()> {
+  let dir = matches.get_one::<PathBuf>("index").expect("required");
+  let (manifest, _) = index::open(dir, SearchOverrides::default())?;
+  print_json(&manifest)
+}
+
+#[derive(Serialize)]
+struct EvalReport {
+  ranking: RankingReport,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  recall: Option<RecallReport>
+}
+
+fn eval(matches: &ArgMatches) -> Result<()> {
+  let dir = matches.get_one::<PathBuf>("index").expect("required");
+  let ks: Vec<u16> = matches.get_many("k").expect("has a default").copied().collect();
+  let (manifest, index) = index::open(dir, search_overrides(matches))?;
+  let held_out = read_held_out::<u64, u64, _>(open_file(matches.get_one::<PathBuf>("held-out").expect("required"))?)?;
+  if held_out.is_empty() {
+    bail!("no held-out pairs to evaluate")
+  }
+  let catalog_size = matches.get_one("catalog-size").copied().or(Some(manifest.n_items));
+  let ranking = evaluate_ranking(index.as_ref(), &held_out, &ks, catalog_size);
+  let recall = match matches.get_one::<String>("exact") {
+    Some(vectors) => {
+      let exact = index::exact(
+        manifest.metric, input::open(vectors, matches)?, manifest.transform.as_ref()
+      )?;
+      let mut seen = HashSet::new();
+      let queries: Vec<u64> = held_out.iter()
+        .map(|pair| pair.subject)
+        .filter(|subject| seen.insert(*subject))
+        .collect();
+      Some(evaluate_recall(index.as_ref(), exact.as_ref(), &queries, &ks))
+    },
+    None => None
+  };
+  print_json(&EvalReport { ranking, recall })
+}
+
+#[cfg(feature = "http")]
+fn serve(matches: &ArgMatches) -> Result<()> {
+  let dir = matches.get_one::<PathBuf>("index").expect("required");
+  let address = *matches.get_one::<SocketAddr>("listen").expect("has a default");
+  let (manifest, index) = index::open(dir, search_overrides(matches))?;
+  let index: Arc<dyn Index> = Arc::from(index);
+  let router = http::router::<u64, u64, _>(index, serde_json::to_value(&manifest)?);
+  let runtime = tokio::runtime::Runtime::new()?;
+  runtime.block_on(async {
+    let listener = tokio::net::TcpListener::bind(address).await
+      .with_context(|| format!("couldn't listen on {}", address))?;
+    eprintln!("Serving {} on http://{}", dir.display(), listener.local_addr()?);
+    axum::serve(listener, router)
+      .with_graceful_shutdown(async {
+        let _ = tokio::signal::ctrl_c().await;
+      })
+      .await?;
+    Ok(())
+  })
+}
+
+fn open_file(path: &Path) -> Result<BufReader<File>> {
+  File::open(path)
+    .map(BufReader::new)
+    .with_context(|| format!("couldn't open {}", path.display()))
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+  let mut out = io::stdout().lock();
+  serde_json::to_writer_pretty(&mut out, value)?;
+  writeln!(out)?;
+  Ok(())
+}
@@ -0,0 +1,308 @@
+use std::{
+  cmp::Ordering,
+  collections::HashMap,
+  hash::Hash
+};
+
+use ndarray::{
+  parallel::prelude::{
+    IntoParallelIterator,
+    ParallelIterator
+  },
+  prelude::{
+    Array1,
+    Array2,
+    ArrayView1,
+    Axis
+  }
+};
+use tracing::{Level, span, debug, trace};
+
+use super::{
+  RecommendRequest,
+  Recommender,
+  RecommendError,
+  RecommendationList,
+  Subject,
+  VectorProvider,
+  VectorRecommender
+};
+use super::score::{
+  Metric,
+  ScoreNormalizer
+};
+use super::spatial::{
+  Distance,
+  NavigableIndex,
+  rank_neighbors,
+  recommend_from_index
+};
+
+/// The number of rows scored per parallel task.
+const ROWS_PER_TASK: usize = 4096;
+
+/// Exact nearest neighbor search, scoring every stored vector for each
+/// query. Suitable for small catalogs, and as ground truth when measuring
+/// the recall of the approximate backends.
+pub struct BruteForceRecommender {
+  /// one row per item. Rows are normalized for the cosine metric, so it can
+  /// be searched by dot product.
+  vectors: Array2<f32>,
+  /// squared L2 norm of each row, for the euclidean metric
+  squared_norms: Array1<f32>,
+  /// the key of each row
+  keys: Vec<usize>,
+  key_to_row: HashMap<usize, usize>,
+  metric: Metric,
+  score_normalizer: ScoreNormalizer,
+  min_score: Option<f32>
+}
+
+impl BruteForceRecommender {
+  pub fn builder<P>() -> BruteForceRecommenderBuilder<P>
+    where P: VectorProvider<usize> {
+    BruteForceRecommenderBuilder::default()
+  }
+
+  pub fn with_score_normalizer(mut self, score_normalizer: ScoreNormalizer) -> Self {
+    self.score_normalizer = score_normalizer;
+    self
+  }
+
+  pub fn with_min_score(mut self, min_score: Option<f32>) -> Self {
+    self.min_score = min_score;
+    self
+  }
+
+  pub fn metric(&self) -> Metric {
+    self.metric
+  }
+
+  /// The dimensionality of the vectors stored in the index.
+  pub fn vector_dimensions(&self) -> usize {
+    self.vectors.dim().1
+  }
+
+  fn check_dimensions(&self, vector: &[f32]) -> Result<(), RecommendError> {
+    if vector.len() != self.vector_dimensions() {
+      return Err(RecommendError::DimensionMismatch {
+        expected: self.vector_dimensions(),
+        received: vector.len()
+      })
+    }
+    Ok(())
+  }
+
+  /// The distance from `subject` to every row, as `1 - cos` for cosine,
+  /// `1 - dot` for dot products and the L2 distance for euclidean.
+  fn distances(&self, subject: &[f32]) -> Array1<f32> {
+    let query = match self.metric {
+      Metric::Cosine => normalized(ArrayView1::from(subject)),
+      _ => ArrayView1::from(subject).to_owned()
+    };
+    // Collecting keeps the chunks in order, and works for an empty catalog
+    let products: Vec<f32> = self.vectors.axis_chunks_iter(Axis(0), ROWS_PER_TASK)
+      .into_par_iter()
+      .flat_map_iter(|chunk| chunk.dot(&query))
+      .collect();
+    let products = Array1::from(products);
+    match self.metric {
+      Metric::Cosine | Metric::Dot => products.mapv(|product| 1f32 - product),
+      // |x - q|^2 = |x|^2 - 2 x.q + |q|^2
+      _ => {
+        let query_norm = query.dot(&query);
+        (&self.squared_norms - &(products * 2f32) + query_norm)
+          .mapv(|squared| squared.max(0f32).sqrt())
+      }
+    }
+  }
+
+  /// The `n_items` rows nearest to `subject`, nearest first. Keys rejected
+  /// by `accept` are skipped.
+  fn nearest(
+    &self, subject: &[f32], n_items: u16, accept: Option<&dyn Fn(&usize) -> bool>
+  ) -> Vec<Distance<usize>> {
+    let distances = self.distances(subject);
+    let by_distance = |this: &usize, other: &usize| -> Ordering {
+      distances[*this].total_cmp(&distances[*other])
+    };
+    let mut rows: Vec<usize> = (0..distances.len())
+      .filter(|row| accept.is_none_or(|accept| accept(&self.keys[*row])))
+      .collect();
+    let n_items = (n_items as usize).min(rows.len());
+    if n_items > 0 && n_items < rows.len() {
+      rows.select_nth_unstable_by(n_items - 1, by_distance);
+      rows.truncate(n_items);
+    }
+    rows.sort_unstable_by(by_distance);
+    trace!("Found {} nearest rows", rows.len());
+    rows.into_iter()
+      .map(|row| Distance::new(self.keys[row], distances[row]))
+      .collect()
+  }
+}
+
+fn normalized(vector: ArrayView1<f32>) -> Array1<f32> {
+  let norm = vector.dot(&vector).sqrt();
+  if norm > 0f32 {
+    vector.mapv(|value| value / norm)
+  } else {
+    vector.to_owned()
+  }
+}
+
+impl<T, Rec> Recommender<T, Rec> for BruteForceRecommender
+  where T: TryInto<usize> + Clone,
+        Rec: From<usize> + PartialEq {
+  fn recommend(&self, item_id: &T, n_items: u16)
+      -> Result<RecommendationList<Rec>, RecommendError> {
+    let span = span!(Level::DEBUG, "brute-force-recommend");
+    let _guard = span.enter();
+    let converted: usize = item_id.clone().try_into()
+      .map_err(|_| RecommendError::IncompatibleId)?;
+    recommend_from_index(self, &converted, n_items)
+  }
+
+  /// The search is exact, so filters and exclusions are applied before
+  /// ranking rather than by over-fetching.
+  fn recommend_with(&self, request: &RecommendRequest<T, Rec>)
+      -> Result<RecommendationList<Rec>, RecommendError>
+    where Rec: Eq + Hash {
+    let span = span!(Level::DEBUG, "brute-force-recommend-with");
+    let _guard = span.enter();
+    let stored;
+    let (subject_key, point) = match request.subject {
+      Subject::Item(item_id) => {
+        let converted: usize = item_id.clone().try_into()
+          .map_err(|_| RecommendError::IncompatibleId)?;
+        stored = self.get_point(&converted)?
+          .ok_or(RecommendError::NotFound)?;
+        (Some(converted), stored.as_slice())
+      },
+      Subject::Vector(vector) => {
+        self.check_dimensions(vector)?;
+        (None, vector)
+      }
+    };
+    let accept = |key: &usize| Some(*key) != subject_key && request.accepts(&Rec::from(*key));
+    let neighbors = self.nearest(point, request.n_ranked(), Some(&accept));
+    Ok(request.finish(rank_neighbors(self, subject_key.as_ref(), neighbors)))
+  }
+}
+
+impl<Rec> VectorRecommender<Rec> for BruteForceRecommender
+  where Rec: From<usize> + PartialEq {
+  fn recommend_by_vector(&self, vector: &[f32], n_items: u16)
+      -> Result<RecommendationList<Rec>, RecommendError> {
+    let span = span!(Level::DEBUG, "brute-force-recommend-by-vector");
+    let _guard = span.enter();
+    self.check_dimensions(vector)?;
+    Ok(rank_neighbors(self, None, self.nearest(vector, n_items, None)))
+  }
+}
+
+impl NavigableIndex for BruteForceRecommender {
+  type Key = usize;
+  type Point = Vec<f32>;
+  type Neighbors = Vec<Distance<usize>>;
+
+  fn get_point(&self, key: &Self::Key) -> Result<Option<Self::Point>, RecommendError> {
+    Ok(self.key_to_row.get(key)
+      .map(|row| self.vectors.row(*row).to_vec()))
+  }
+
+  fn search(&self, subject: &Self::Point, n_items: u16) -> Result<Self::Neighbors, RecommendError> {
+    self.check_dimensions(subject)?;
+    Ok(self.nearest(subject, n_items, None))
+  }
+
+  fn score(&self, distance: f32) -> f32 {
+    let measure = match self.metric {
+      Metric::Cosine | Metric::Dot => 1f32 - distance,
+      _ => distance
+    };
+    self.score_normalizer.normalize(self.metric, measure)
+  }
+
+  fn min_score(&self) -> Option<f32> {
+    self.min_score
+  }
+}
+
+/// Arguments for building a [`BruteForceRecommender`].
+#[derive(Builder)]
+#[builder(name = "BruteForceRecommenderBuilder", pattern="owned", public, build_fn(skip))]
+#[allow(dead_code)]
+pub struct BruteForceRecommenderArguments<P>
+  where P: VectorProvider<usize> {
+  /// cosine, dot or euclidean
+  metric: Metric,
+  /// how distances are turned into scores
+  #[builder(default)]
+  score_normalizer: ScoreNormalizer,
+  /// applied to every request, see
+  /// [`RecommendRequest::min_score`](crate::RecommendRequest::min_score)
+  #[builder(default, setter(strip_option))]
+  min_score: Option<f32>,
+  vector_provider: P
+}
+
+impl<P> BruteForceRecommenderBuilder<P>
+  where P: VectorProvider<usize> {
+  pub fn build(self) -> Result<BruteForceRecommender, BruteForceRecommenderBuilderError> {
+    let span = span!(Level::DEBUG, "brute-force-init");
+    let _guard = span.enter();
+    let metric = Self::unwrap_field(self.metric, "metric")?;
+    if metric == Metric::Manhattan {
+      return Err(BruteForceRecommenderBuilderError::ValidationError(
+        "the manhattan metric isn't supported".to_string()
+      ))
+    }
+    let provider = Self::unwrap_field(self.vector_provider, "vector_provider")?;
+    let dims = provider.vector_dimensions() as usize;
+    let (n_expected, _) = provider.size_hint();
+    let mut keys = Vec::with_capacity(n_expected);
+    let mut key_to_row = HashMap::with_capacity(n_expected);
+    let mut values = Vec::with_capacity(n_expected * dims);
+    debug!("Consuming vector provider");
+    for keyed_vector in provider {
+      if keyed_vector.vector.len() != dims {
+        return Err(BruteForceRecommenderBuilderError::ValidationError(format!(
+          "vector for key {} has {} dimensions, expected {}",
+          keyed_vector.key, keyed_vector.vector.len(), dims
+        )))
+      }
+      if key_to_row.insert(keyed_vector.key, keys.len()).is_some() {
+        return Err(BruteForceRecommenderBuilderError::ValidationError(
+          format!("duplicate key {}", keyed_vector.key)
+        ))
+      }
+      keys.push(keyed_vector.key);
+      values.extend(keyed_vector.vector);
+    }
+    let mut vectors = Array2::from_shape_vec((keys.len(), dims), values)
+      .map_err(|e| BruteForceRecommenderBuilderError::ValidationError(e.to_string()))?;
+    if metric == Metric::Cosine {
+      debug!("Normalizing vectors");
+      for mut row in vectors.axis_iter_mut(Axis(0)) {
+        let normalized = normalized(row.view());
+        row.assign(&normalized);
+      }
+    }
+    let squared_norms = vectors.map_axis(Axis(1), |row| row.dot(&row));
+    debug!("Index initialized with {} vectors", keys.len());
+    Ok(BruteForceRecommender {
+      vectors,
+      squared_norms,
+      keys,
+      key_to_row,
+      metric,
+      score_normalizer: self.score_normalizer.unwrap_or_default(),
+      min_score: self.min_score.flatten()
+    })
+  }
+
+  fn unwrap_field<T>(val: Option<T>, name: &'static str) -> Result<T, BruteForceRecommenderBuilderError> {
+    val.ok_or(BruteForceRecommenderBuilderError::UninitializedField(name))
+  }
+}
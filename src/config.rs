@@ -0,0 +1,660 @@
+//! Recommenders described by configuration files rather than code, so index
+//! settings can change without a rebuild. A TOML config looks like:
+//!
+//! ```toml
+//! metric = "cosine"
+//!
+//! [backend]
+//! kind = "hnsw"
+//! max_connections = 24
+//! ef_construction = 400
+//!
+//! [query]
+//! ef_search = 128
+//!
+//! [source]
+//! path = "vectors.csv"
+//! header = true
+//!
+//! [[transform]]
+//! kind = "normalize"
+//! ```
+//!
+//! YAML and JSON configs have the same structure. Transform steps are
+//! applied to the source vectors and to query vectors, and are saved next to
+//! persisted indexes.
+
+use std::{
+  fmt::Debug,
+  fs::{self, File},
+  hash::Hash,
+  io::{BufReader, ErrorKind},
+  path::{Path, PathBuf},
+  str::FromStr
+};
+
+use serde::{
+  Deserialize,
+  Serialize,
+  de::DeserializeOwned
+};
+use thiserror::Error;
+use tracing::{Level, span, debug};
+
+use super::{
+  AnnoyRecommender,
+  BruteForceRecommender,
+  DynRecommender,
+  HnswRecommender,
+  VectorProvider,
+  annoy_recommender::{AnnoyRecommenderBuilderError, distances},
+  brute_force::BruteForceRecommenderBuilderError,
+  hnsw_recommender::{
+    HnswDistance,
+    HnswRecommenderBuilderError,
+    PersistError,
+    check_dump_layers,
+    dist
+  },
+  mapping::RekeyedRecommender,
+  providers::{
+    DelimitedFormat,
+    ProviderError,
+    StreamStatus,
+    VectorStream,
+    delimited,
+    jsonl,
+    npy,
+    vecs
+  },
+  score::{Metric, ScoredDistance},
+  transform::{
+    TransformError,
+    TransformPipeline,
+    TransformedProvider,
+    TransformingRecommender,
+    VectorTransform
+  }
+};
+
+/// The file a transform pipeline is saved to, next to a persisted index.
+const PIPELINE_FILE: &str = "transform.json";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+  #[error("couldn't read config or vectors")]
+  Io(#[from] std::io::Error),
+  #[error("invalid TOML config: {0}")]
+  Toml(#[from] toml::de::Error),
+  #[error("invalid YAML config: {0}")]
+  Yaml(#[from] serde_yaml::Error),
+  #[error("invalid JSON config: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("can't tell the format of {0}")]
+  UnknownFormat(PathBuf),
+  #[error("the {0} backend needs a vector source")]
+  MissingSource(&'static str),
+  #[error("the {backend} backend doesn't support the {metric:?} metric")]
+  UnsupportedMetric { backend: &'static str, metric: Metric },
+  #[error("npy sources need a keys file")]
+  MissingKeys,
+  #[error("couldn't read vectors: {0}")]
+  Provider(#[from] ProviderError),
+  #[error("couldn't build arroy index: {0}")]
+  Annoy(#[from] AnnoyRecommenderBuilderError),
+  #[error("couldn't build hnsw index: {0}")]
+  Hnsw(#[from] HnswRecommenderBuilderError),
+  #[error("couldn't persist hnsw index: {0}")]
+  HnswPersist(#[from] PersistError),
+  #[error("couldn't build brute force index: {0}")]
+  BruteForce(#[from] BruteForceRecommenderBuilderError),
+  #[error("invalid transform: {0}")]
+  Transform(#[from] TransformError),
+  #[error("invalid transform saved with the index: {0}")]
+  SavedTransform(serde_json::Error),
+  #[error("the index in {0} was built with a different transform")]
+  TransformMismatch(PathBuf)
+}
+
+/// The languages a config can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+  Toml,
+  Yaml,
+  Json
+}
+
+impl ConfigFormat {
+  /// Guess the format from a `.toml`, `.yaml`, `.yml` or `.json` extension.
+  pub fn from_path(path: &Path) -> Option<Self> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+      "toml" => Some(ConfigFormat::Toml),
+      "yaml" | "yml" => Some(ConfigFormat::Yaml),
+      "json" => Some(ConfigFormat::Json),
+      _ => None
+    }
+  }
+}
+
+/// How to build a recommender and serve queries from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecommenderConfig {
+  pub metric: Metric,
+  pub backend: BackendConfig,
+  #[serde(default)]
+  pub query: QueryConfig,
+  /// preprocessing steps applied to the source vectors and to query
+  /// vectors. They're saved next to persisted indexes and reapplied when
+  /// the index is reopened.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub transform: Vec<VectorTransform>,
+  /// the vectors to index. Backends that persist their index reopen it
+  /// when this is unset.
+  pub source: Option<SourceConfig>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BackendConfig {
+  /// an arroy database, built into `path` or reopened from it
+  Annoy {
+    path: PathBuf,
+    /// the LMDB map size in bytes, bounding the database size
+    #[serde(default = "default_map_size")]
+    map_size: usize,
+    #[serde(default = "default_max_dbs")]
+    max_dbs: usize,
+    /// if unset, arroy picks a count based on the data
+    n_trees: Option<usize>,
+    seed: Option<u64>
+  },
+  /// an hnsw graph. If `path` is set, built graphs are dumped there, and
+  /// the dump is reloaded when there's no source.
+  Hnsw {
+    path: Option<PathBuf>,
+    #[serde(default = "default_max_connections")]
+    max_connections: usize,
+    #[serde(default = "default_n_layers")]
+    n_layers: usize,
+    /// the search width while building
+    #[serde(default = "default_ef_construction")]
+    ef_construction: usize
+  },
+  BruteForce
+}
+
+fn default_map_size() -> usize {
+  1 << 32
+}
+
+fn default_max_dbs() -> usize {
+  1
+}
+
+fn default_max_connections() -> usize {
+  16
+}
+
+fn default_n_layers() -> usize {
+  16
+}
+
+fn default_ef_construction() -> usize {
+  200
+}
+
+impl BackendConfig {
+  pub fn name(&self) -> &'static str {
+    match self {
+      BackendConfig::Annoy { .. } => "annoy",
+      BackendConfig::Hnsw { .. } => "hnsw",
+      BackendConfig::BruteForce => "brute_force"
+    }
+  }
+}
+
+/// Query-time defaults. Backends ignore the parameters that don't apply to
+/// them.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueryConfig {
+  /// the number of tree nodes arroy inspects per query
+  pub search_k: Option<usize>,
+  /// the hnsw search width per query
+  pub ef_search: Option<usize>,
+  /// the backend's default cutoff, see
+  /// [`RecommendRequest::min_score`](crate::RecommendRequest::min_score)
+  pub min_score: Option<f32>
+}
+
+/// A vector file and its layout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+  pub path: PathBuf,
+  /// guessed from the extension if unset
+  pub format: Option<VectorFormat>,
+  /// the key file of npy matrices, with one key per row
+  pub keys: Option<PathBuf>,
+  /// the zero-based key column of CSV and TSV files
+  #[serde(default)]
+  pub key_column: usize,
+  /// whether CSV and TSV files start with a header row
+  #[serde(default)]
+  pub header: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorFormat {
+  Csv,
+  Tsv,
+  Jsonl,
+  Fvecs,
+  Bvecs,
+  Npy
+}
+
+impl VectorFormat {
+  pub fn from_path(path: &Path) -> Option<Self> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+      "csv" => Some(VectorFormat::Csv),
+      "tsv" => Some(VectorFormat::Tsv),
+      "jsonl" => Some(VectorFormat::Jsonl),
+      "fvecs" => Some(VectorFormat::Fvecs),
+      "bvecs" => Some(VectorFormat::Bvecs),
+      "npy" => Some(VectorFormat::Npy),
+      _ => None
+    }
+  }
+}
+
+impl SourceConfig {
+  /// Stream the source's vectors.
+  pub fn open<K>(&self) -> Result<VectorStream<K>, ConfigError>
+    where K: FromStr + DeserializeOwned + TryFrom<usize> + 'static {
+    let format = self.format
+      .or_else(|| VectorFormat::from_path(&self.path))
+      .ok_or_else(|| ConfigError::UnknownFormat(self.path.clone()))?;
+    let input = BufReader::new(File::open(&self.path)?);
+    let delimited_format = |format: DelimitedFormat| {
+      format.with_key_column(self.key_column).with_header(self.header)
+    };
+    let stream = match format {
+      VectorFormat::Csv => delimited::stream(input, delimited_format(DelimitedFormat::csv())),
+      VectorFormat::Tsv => delimited::stream(input, delimited_format(DelimitedFormat::tsv())),
+      VectorFormat::Jsonl => jsonl::stream(input),
+      VectorFormat::Fvecs => vecs::stream_fvecs(input),
+      VectorFormat::Bvecs => vecs::stream_bvecs(input),
+      VectorFormat::Npy => {
+        let keys = self.keys.as_ref().ok_or(ConfigError::MissingKeys)?;
+        // The file's length is checked against the header's shape
+        npy::stream_file(input.into_inner(), BufReader::new(File::open(keys)?))
+      }
+    }?;
+    Ok(stream)
+  }
+}
+
+impl FromStr for RecommenderConfig {
+  type Err = ConfigError;
+
+  /// Parse a TOML config.
+  fn from_str(config: &str) -> Result<Self, ConfigError> {
+    Self::parse(config, ConfigFormat::Toml)
+  }
+}
+
+impl RecommenderConfig {
+  pub fn parse(config: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+    Ok(match format {
+      ConfigFormat::Toml => toml::from_str(config)?,
+      ConfigFormat::Yaml => serde_yaml::from_str(config)?,
+      ConfigFormat::Json => serde_json::from_str(config)?
+    })
+  }
+
+  /// Read a config file, in the format its extension names.
+  pub fn from_path<PathRef>(path: PathRef) -> Result<Self, ConfigError>
+    where PathRef: AsRef<Path> {
+    let path = path.as_ref();
+    let format = ConfigFormat::from_path(path)
+      .ok_or_else(|| ConfigError::UnknownFormat(path.to_path_buf()))?;
+    Self::parse(&fs::read_to_string(path)?, format)
+  }
+
+  /// Build the configured recommender, or reopen its persisted index.
+  ///
+  /// The backends key items by `u32` (annoy) or `usize` (hnsw and brute
+  /// force); `K` and `R` are converted to and from those, so `u32` and
+  /// `u64` work with every backend.
+  pub fn build<K, R>(&self) -> Result<Box<DynRecommender<K, R>>, ConfigError>
+    where K: TryInto<u32> + TryInto<usize> + Debug + Clone + 'static,
+          R: From<u32> + TryInto<u32> + TryFrom<usize> + TryInto<usize>
+            + Clone + Eq + Hash + Send + Sync + 'static {
+    let span = span!(Level::DEBUG, "config-build");
+    let _guard = span.enter();
+    debug!("Building {} recommender with the {:?} metric", self.backend.name(), self.metric);
+    match &self.backend {
+      BackendConfig::Annoy { path, map_size, max_dbs, n_trees, seed } => {
+        let params = AnnoyParams {
+          path,
+          map_size: *map_size,
+          max_dbs: *max_dbs,
+          n_trees: *n_trees,
+          seed: *seed
+        };
+        match self.metric {
+          Metric::Cosine => self.build_annoy::<distances::Angular, K, R>(params),
+          Metric::Euclidean => self.build_annoy::<distances::Euclidean, K, R>(params),
+          Metric::Manhattan => self.build_annoy::<distances::Manhattan, K, R>(params),
+          Metric::Dot => self.build_annoy::<distances::DotProduct, K, R>(params)
+        }
+      },
+      BackendConfig::Hnsw { path, max_connections, n_layers, ef_construction } => {
+        let params = HnswParams {
+          path: path.as_deref(),
+          max_connections: *max_connections,
+          n_layers: *n_layers,
+          ef_construction: *ef_construction
+        };
+        match self.metric {
+          Metric::Cosine => self.build_hnsw(params, dist::DistCosine),
+          Metric::Euclidean => self.build_hnsw(params, dist::DistL2),
+          Metric::Manhattan => self.build_hnsw(params, dist::DistL1),
+          // hnsw_rs's dot distance asserts unit norm vectors, and panics otherwise
+          Metric::Dot => Err(ConfigError::UnsupportedMetric {
+            backend: self.backend.name(),
+            metric: self.metric
+          })
+        }
+      },
+      BackendConfig::BruteForce => {
+        let source = self.source.as_ref()
+          .ok_or(ConfigError::MissingSource(self.backend.name()))?;
+        let (vectors, status) = self.open_source::<usize>(source)?;
+        let pipeline = vectors.pipeline().clone();
+        let mut builder = BruteForceRecommender::builder()
+          .metric(self.metric)
+          .vector_provider(vectors);
+        if let Some(min_score) = self.query.min_score {
+          builder = builder.min_score(min_score);
+        }
+        let recommender = builder.build()?;
+        status.check()?;
+        Ok(transforming(Box::new(RekeyedRecommender::<_, usize>::new(recommender)), Some(pipeline)))
+      }
+    }
+  }
+
+  /// Open `source` with the configured transform applied to its vectors.
+  fn open_source<K>(&self, source: &SourceConfig)
+      -> Result<(TransformedProvider<VectorStream<K>>, StreamStatus), ConfigError>
+    where K: FromStr + DeserializeOwned + TryFrom<usize> + 'static {
+    let vectors = source.open::<K>()?;
+    let status = vectors.status();
+    let pipeline = TransformPipeline::with_steps(
+      vectors.vector_dimensions(), self.transform.iter().cloned()
+    )?;
+    Ok((pipeline.wrap(vectors)?, status))
+  }
+
+  /// The pipeline saved with the index in `dir`, which must have the
+  /// configured steps if any are configured.
+  fn load_pipeline(&self, dir: &Path) -> Result<Option<TransformPipeline>, ConfigError> {
+    let pipeline: Option<TransformPipeline> = match fs::read(dir.join(PIPELINE_FILE)) {
+      Ok(saved) => Some(serde_json::from_slice(&saved).map_err(ConfigError::SavedTransform)?),
+      Err(e) if e.kind() == ErrorKind::NotFound => None,
+      Err(e) => return Err(e.into())
+    };
+    let steps = pipeline.as_ref().map_or(&[][..], TransformPipeline::steps);
+    if !self.transform.is_empty() && steps != self.transform.as_slice() {
+      return Err(ConfigError::TransformMismatch(dir.to_path_buf()))
+    }
+    Ok(pipeline)
+  }
+
+  fn build_annoy<D, K, R>(&self, params: AnnoyParams)
+      -> Result<Box<DynRecommender<K, R>>, ConfigError>
+    where D: arroy::Distance + ScoredDistance,
+          K: TryInto<u32> + Debug + Clone + 'static,
+          R: From<u32> + TryInto<u32> + Clone + PartialEq + 'static {
+    if self.source.is_some() {
+      // LMDB creates the database files, but not their directory
+      fs::create_dir_all(params.path)?;
+    }
+    let (vectors, status) = self.source.as_ref()
+      .map(|source| self.open_source::<u32>(source))
+      .transpose()?
+      .unzip();
+    let pipeline = vectors.as_ref().map(|vectors| vectors.pipeline().clone());
+    let mut builder = AnnoyRecommender::<D>::builder()
+      .map_size(params.map_size)
+      .max_dbs(params.max_dbs)
+      .path(params.path)
+      .n_trees(params.n_trees.unwrap_or(0))
+      .search_k(self.query.search_k.unwrap_or(0))
+      .vector_provider(vectors);
+    if let Some(seed) = params.seed {
+      builder = builder.seed(seed);
+    }
+    if let Some(min_score) = self.query.min_score {
+      builder = builder.min_score(min_score);
+    }
+    let recommender = builder.build()?;
+    status.map(|status| status.check()).transpose()?;
+    let pipeline = match pipeline {
+      Some(pipeline) => {
+        save_pipeline(params.path, &pipeline)?;
+        Some(pipeline)
+      },
+      None => self.load_pipeline(params.path)?
+    };
+    Ok(transforming(Box::new(recommender), pipeline))
+  }
+
+  fn build_hnsw<D, K, R>(&self, params: HnswParams, metric: D)
+      -> Result<Box<DynRecommender<K, R>>, ConfigError>
+    where D: HnswDistance<f32> + ScoredDistance + Send + Sync + 'static,
+          K: TryInto<usize> + Clone + 'static,
+          R: TryFrom<usize> + TryInto<usize> + Clone + Eq + Hash + Send + Sync + 'static {
+    let (recommender, pipeline) = match (&self.source, params.path) {
+      (Some(source), path) => {
+        if path.is_some() {
+          check_dump_layers(params.n_layers)?;
+        }
+        let (vectors, status) = self.open_source::<usize>(source)?;
+        let pipeline = vectors.pipeline().clone();
+        let mut builder = HnswRecommender::builder()
+          .max_connections(params.max_connections)
+          .n_layers(params.n_layers)
+          .ef_coef(params.ef_construction)
+          .ef_search(self.query.ef_search.unwrap_or(0))
+          .metric(metric)
+          .vector_provider(vectors);
+        if let Some(min_score) = self.query.min_score {
+          builder = builder.min_score(min_score);
+        }
+        let recommender = builder.build()?;
+        status.check()?;
+        if let Some(path) = path {
+          debug!("Dumping graph to {:?}", path);
+          recommender.save(path)?;
+          save_pipeline(path, &pipeline)?;
+        }
+        (recommender, Some(pipeline))
+      },
+      (None, Some(path)) => {
+        // Dumps keep the settings they were built with unless overridden
+        let mut recommender = HnswRecommender::load(path, metric)?;
+        if let Some(ef_search) = self.query.ef_search {
+          recommender = recommender.with_ef_search(Some(ef_search));
+        }
+        if let Some(min_score) = self.query.min_score {
+          recommender = recommender.with_min_score(Some(min_score));
+        }
+        (recommender, self.load_pipeline(path)?)
+      },
+      (None, None) => return Err(ConfigError::MissingSource(self.backend.name()))
+    };
+    Ok(transforming(Box::new(RekeyedRecommender::<_, usize>::new(recommender)), pipeline))
+  }
+}
+
+/// Save `pipeline` with the index in `dir`, or remove a stale one if it
+/// has no steps.
+fn save_pipeline(dir: &Path, pipeline: &TransformPipeline) -> Result<(), ConfigError> {
+  let path = dir.join(PIPELINE_FILE);
+  if pipeline.is_empty() {
+    return match fs::remove_file(path) {
+      Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+      _ => Ok(())
+    }
+  }
+  fs::write(path, serde_json::to_vec_pretty(pipeline).map_err(ConfigError::SavedTransform)?)?;
+  Ok(())
+}
+
+/// Transform the recommender's query vectors with `pipeline`, unless it
+/// leaves them as they are.
+fn transforming<K, R>(recommender: Box<DynRecommender<K, R>>, pipeline: Option<TransformPipeline>)
+    -> Box<DynRecommender<K, R>>
+  where K: 'static,
+        R: 'static {
+  match pipeline.filter(|pipeline| !pipeline.is_empty()) {
+    Some(pipeline) => Box::new(TransformingRecommender::new(pipeline, recommender)),
+    None => recommender
+  }
+}
+
+/// The annoy backend's build parameters, borrowed from the config.
+struct AnnoyParams<'a> {
+  path: &'a Path,
+  map_size: usize,
+  max_dbs: usize,
+  n_trees: Option<usize>,
+  seed: Option<u64>
+}
+
+struct HnswParams<'a> {
+  path: Option<&'a Path>,
+  max_connections: usize,
+  n_layers: usize,
+  ef_construction: usize
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, process};
+
+  use super::*;
+
+  const TOML: &str = r#"
+    metric = "euclidean"
+
+    [backend]
+    kind = "hnsw"
+    max_connections = 24
+
+    [query]
+    ef_search = 128
+
+    [source]
+    path = "vectors.csv"
+    header = true
+
+    [[transform]]
+    kind = "resize"
+    dimensions = 2
+  "#;
+
+  const YAML: &str = "
+metric: euclidean
+backend:
+  kind: hnsw
+  max_connections: 24
+query:
+  ef_search: 128
+source:
+  path: vectors.csv
+  header: true
+transform:
+  - kind: resize
+    dimensions: 2
+";
+
+  const JSON: &str = r#"{
+    "metric": "euclidean",
+    "backend": { "kind": "hnsw", "max_connections": 24 },
+    "query": { "ef_search": 128 },
+    "source": { "path": "vectors.csv", "header": true },
+    "transform": [{ "kind": "resize", "dimensions": 2 }]
+  }"#;
+
+  fn temp_dir(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("recommender-config-{}-{}", name, process::id()))
+  }
+
+  #[test]
+  fn formats_share_a_structure() {
+    let toml = TOML.parse::<RecommenderConfig>().unwrap();
+    let BackendConfig::Hnsw { path, max_connections, n_layers, .. } = &toml.backend else {
+      panic!("expected an hnsw backend")
+    };
+    assert_eq!((path, *max_connections, *n_layers), (&None, 24, 16));
+    assert_eq!(toml.query.ef_search, Some(128));
+    assert_eq!(toml.transform, vec![VectorTransform::Resize { dimensions: 2 }]);
+    let source = toml.source.as_ref().unwrap();
+    assert_eq!((source.path.as_path(), source.header, source.key_column), (Path::new("vectors.csv"), true, 0));
+    let toml = serde_json::to_value(toml).unwrap();
+    for (config, format) in [(YAML, ConfigFormat::Yaml), (JSON, ConfigFormat::Json)] {
+      let parsed = RecommenderConfig::parse(config, format).unwrap();
+      assert_eq!(serde_json::to_value(parsed).unwrap(), toml);
+    }
+  }
+
+  #[test]
+  fn unknown_fields_are_rejected() {
+    let typo = TOML.replace("max_connections", "max_conections");
+    assert!(matches!(typo.parse::<RecommenderConfig>(), Err(ConfigError::Toml(_))));
+    let typo = JSON.replace("\"header\"", "\"headers\"");
+    assert!(matches!(RecommenderConfig::parse(&typo, ConfigFormat::Json), Err(ConfigError::Json(_))));
+    let extra = format!("{}extra: 1\n", YAML);
+    assert!(matches!(RecommenderConfig::parse(&extra, ConfigFormat::Yaml), Err(ConfigError::Yaml(_))));
+  }
+
+  #[test]
+  fn formats_follow_extensions() {
+    assert_eq!(ConfigFormat::from_path(Path::new("a.YML")), Some(ConfigFormat::Yaml));
+    assert_eq!(ConfigFormat::from_path(Path::new("a.cfg")), None);
+    assert!(matches!(RecommenderConfig::from_path("a.cfg"), Err(ConfigError::UnknownFormat(_))));
+  }
+
+  #[test]
+  fn hnsw_rejects_the_dot_metric() {
+    let config = TOML.replace("euclidean", "dot").parse::<RecommenderConfig>().unwrap();
+    let result = config.build::<u32, u32>();
+    assert!(matches!(result, Err(ConfigError::UnsupportedMetric { backend: "hnsw", metric: Metric::Dot })));
+  }
+
+  #[test]
+  fn annoy_builds_into_a_new_directory() {
+    let dir = temp_dir("annoy");
+    let vectors = dir.join("vectors.csv");
+    let path = dir.join("index").join("nested");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(&vectors, "1,1,0\n2,0.9,0.1\n3,0,1\n").unwrap();
+    let config = RecommenderConfig::parse(&format!(
+      "metric = \"cosine\"\n[backend]\nkind = \"annoy\"\npath = {:?}\nmap_size = 10485760\n\
+       [source]\npath = {:?}\n",
+      path, vectors
+    ), ConfigFormat::Toml).unwrap();
+    let recs = config.build::<u32, u32>().map(|recommender| recommender.recommend(&1, 1));
+    fs::remove_dir_all(&dir).unwrap();
+    let item_ids = recs.unwrap().unwrap().0.iter().map(|rec| rec.item_id).collect::<Vec<_>>();
+    assert_eq!(item_ids, vec![2]);
+  }
+}
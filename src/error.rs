@@ -11,5 +11,12 @@ pub enum RecommendError {
   #[error("incompatible ID type for operatation")]
   IncompatibleId,
   #[error("vector not found")]
-  NotFound
+  NotFound,
+  #[error("request not supported by this recommender")]
+  UnsupportedRequest,
+  #[error("query vector has {received} dimensions, index expects {expected}")]
+  DimensionMismatch {
+    expected: usize,
+    received: usize
+  }
 }
@@ -0,0 +1,112 @@
+pub mod ranking;
+pub mod recall;
+
+use std::time::Duration;
+
+use serde::Serialize;
+use thiserror::Error;
+
+pub use ranking::{
+  HeldOut,
+  RankingAtK,
+  RankingReport,
+  evaluate_ranking,
+  read_held_out
+};
+pub use recall::{
+  RecallAtK,
+  RecallReport,
+  evaluate_recall
+};
+
+#[derive(Debug, Error)]
+pub enum EvalError {
+  #[error("could not read evaluation data")]
+  Io(#[from] std::io::Error),
+  #[error("line {line}: {message}")]
+  Parse {
+    line: usize,
+    message: String
+  }
+}
+
+/// Percentiles of the time taken by a recommender to answer queries.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencySummary {
+  pub mean: Duration,
+  pub p50: Duration,
+  pub p90: Duration,
+  pub p99: Duration,
+  pub max: Duration
+}
+
+impl LatencySummary {
+  pub fn from_samples(mut samples: Vec<Duration>) -> Self {
+    if samples.is_empty() {
+      return Self::default()
+    }
+    samples.sort_unstable();
+    let total: Duration = samples.iter().sum();
+    LatencySummary {
+      mean: total / samples.len() as u32,
+      p50: percentile(&samples, 0.5),
+      p90: percentile(&samples, 0.9),
+      p99: percentile(&samples, 0.99),
+      max: samples[samples.len() - 1]
+    }
+  }
+}
+
+/// The nearest-rank percentile of sorted, non-empty `samples`.
+fn percentile(samples: &[Duration], fraction: f64) -> Duration {
+  let rank = (fraction * samples.len() as f64).ceil() as usize;
+  samples[rank.clamp(1, samples.len()) - 1]
+}
+
+fn mean(sum: f64, count: usize) -> f64 {
+  if count == 0 { 0f64 } else { sum / count as f64 }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+  use crate::{Recommendation, RecommendationList, RecommendError, Recommender};
+
+  /// Answers each subject with a fixed list, scored in descending order,
+  /// and fails for unknown subjects.
+  pub(super) struct Fixed(HashMap<u32, Vec<u32>>);
+
+  impl Fixed {
+    pub fn new(lists: &[(u32, &[u32])]) -> Self {
+      Fixed(lists.iter().map(|(subject, items)| (*subject, items.to_vec())).collect())
+    }
+  }
+
+  impl Recommender<u32, u32> for Fixed {
+    fn recommend(&self, subject: &u32, n_items: u16) -> Result<RecommendationList<u32>, RecommendError> {
+      let items = self.0.get(subject).ok_or(RecommendError::NotFound)?;
+      Ok(RecommendationList(items.iter()
+        .take(n_items as usize)
+        .enumerate()
+        .map(|(i, item)| Recommendation::new(*item, 1f32 - i as f32 / 10f32))
+        .collect()))
+    }
+  }
+
+  pub(super) fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+  }
+
+  #[test]
+  fn latency_percentiles_use_nearest_rank() {
+    let samples = (1..=10).map(Duration::from_millis).collect::<Vec<_>>();
+    let summary = LatencySummary::from_samples(samples);
+    assert_eq!(summary.p50, Duration::from_millis(5));
+    assert_eq!(summary.p90, Duration::from_millis(9));
+    assert_eq!(summary.p99, Duration::from_millis(10));
+    assert_eq!(summary.mean, Duration::from_micros(5500));
+    assert_eq!(LatencySummary::from_samples(Vec::new()).max, Duration::ZERO);
+  }
+}
@@ -0,0 +1,241 @@
+use std::{
+  collections::{HashMap, HashSet},
+  hash::Hash,
+  io::BufRead,
+  str::FromStr
+};
+
+use serde::Serialize;
+use tracing::{Level, span, debug, warn};
+
+use super::{EvalError, mean};
+use crate::{
+  Recommender,
+  RecommendationList
+};
+
+/// An item a subject was observed to interact with, held out from the data
+/// the recommender was built on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldOut<K, R> {
+  pub subject: K,
+  pub item: R
+}
+
+impl<K, R> HeldOut<K, R> {
+  pub fn new(subject: K, item: R) -> Self {
+    HeldOut { subject, item }
+  }
+}
+
+/// Read held-out pairs, one `subject,item` or `subject<TAB>item` per line.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn read_held_out<K, R, Rd>(input: Rd) -> Result<Vec<HeldOut<K, R>>, EvalError>
+  where K: FromStr,
+        R: FromStr,
+        Rd: BufRead {
+  let mut pairs = Vec::new();
+  for (i, line) in input.lines().enumerate() {
+    let line = line?;
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue
+    }
+    let parse_error = |message: &str| EvalError::Parse {
+      line: i + 1,
+      message: message.to_string()
+    };
+    let (subject, item) = line.split_once(['\t', ','])
+      .ok_or_else(|| parse_error("expected a subject and an item"))?;
+    pairs.push(HeldOut::new(
+      subject.trim().parse().map_err(|_| parse_error("invalid subject"))?,
+      item.trim().parse().map_err(|_| parse_error("invalid item"))?
+    ));
+  }
+  Ok(pairs)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RankingAtK {
+  pub k: u16,
+  /// the share of pairs whose item was in the top `k`
+  pub hit_rate: f64,
+  /// the mean NDCG of the top `k`, each pair having one relevant item
+  pub ndcg: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RankingReport {
+  /// one entry per requested `k`, in ascending order
+  pub ranking: Vec<RankingAtK>,
+  /// the mean reciprocal rank of the held-out item within the top largest
+  /// `k`, counting misses as zero
+  pub mrr: f64,
+  /// the number of distinct items recommended across all queries
+  pub n_recommended_items: usize,
+  /// the share of the catalog that was recommended at least once, if the
+  /// catalog size is known
+  pub coverage: Option<f64>,
+  /// the mean popularity of recommended items, popularity being how often
+  /// an item appears among the held-out pairs
+  pub average_popularity: f64,
+  /// `average_popularity` relative to the mean popularity of the held-out
+  /// items themselves. Above 1 the recommender favors popular items.
+  pub popularity_bias: f64,
+  /// the number of pairs the recommender answered
+  pub n_queries: usize,
+  /// the number of pairs skipped because the recommender failed
+  pub n_failed: usize
+}
+
+/// Measure how well `recommender` ranks the held-out items of each subject.
+/// The recommender is asked once per pair for the largest `k`; coverage and
+/// popularity are measured on those lists.
+pub fn evaluate_ranking<K, R, Rc>(
+  recommender: &Rc, held_out: &[HeldOut<K, R>], ks: &[u16], catalog_size: Option<usize>
+) -> RankingReport
+  where Rc: Recommender<K, R> + ?Sized,
+        R: Eq + Hash {
+  let span = span!(Level::DEBUG, "evaluate-ranking");
+  let _guard = span.enter();
+  let mut ks = ks.to_vec();
+  ks.sort_unstable();
+  ks.dedup();
+  let max_k = ks.last().copied().unwrap_or(0);
+  debug!("Evaluating ranking@{:?} over {} pairs", ks, held_out.len());
+  let mut popularity: HashMap<&R, usize> = HashMap::new();
+  for pair in held_out {
+    *popularity.entry(&pair.item).or_default() += 1;
+  }
+  let popularity_of = |item: &R| popularity.get(item).copied().unwrap_or(0) as f64;
+  let mut hit_sums = vec![0f64; ks.len()];
+  let mut ndcg_sums = vec![0f64; ks.len()];
+  let mut reciprocal_rank_sum = 0f64;
+  let mut recommended: HashSet<R> = HashSet::new();
+  let mut popularity_sum = 0f64;
+  let mut n_recommendations = 0usize;
+  let mut held_out_popularity_sum = 0f64;
+  let mut n_failed = 0;
+  for pair in held_out {
+    let RecommendationList(recs) = match recommender.recommend(&pair.subject, max_k) {
+      Ok(recs) => recs,
+      Err(e) => {
+        warn!("Skipping pair: {}", e);
+        n_failed += 1;
+        continue
+      }
+    };
+    held_out_popularity_sum += popularity_of(&pair.item);
+    // 1-based rank of the held-out item, if it was recommended
+    let rank = recs.iter()
+      .position(|rec| rec.item_id == pair.item)
+      .map(|position| position + 1);
+    if let Some(rank) = rank {
+      reciprocal_rank_sum += 1f64 / rank as f64;
+    }
+    for (i, k) in ks.iter().enumerate() {
+      if let Some(rank) = rank.filter(|rank| *rank <= *k as usize) {
+        hit_sums[i] += 1f64;
+        // the ideal ranking puts the only relevant item first, for a DCG of 1
+        ndcg_sums[i] += 1f64 / (rank as f64 + 1f64).log2();
+      }
+    }
+    for rec in recs {
+      popularity_sum += popularity_of(&rec.item_id);
+      n_recommendations += 1;
+      recommended.insert(rec.item_id);
+    }
+  }
+  let n_queries = held_out.len() - n_failed;
+  let ranking = ks.into_iter()
+    .enumerate()
+    .map(|(i, k)| RankingAtK {
+      k,
+      hit_rate: mean(hit_sums[i], n_queries),
+      ndcg: mean(ndcg_sums[i], n_queries)
+    })
+    .collect();
+  let average_popularity = mean(popularity_sum, n_recommendations);
+  let held_out_popularity = mean(held_out_popularity_sum, n_queries);
+  RankingReport {
+    ranking,
+    mrr: mean(reciprocal_rank_sum, n_queries),
+    n_recommended_items: recommended.len(),
+    coverage: catalog_size
+      .filter(|size| *size > 0)
+      .map(|size| recommended.len() as f64 / size as f64),
+    average_popularity,
+    popularity_bias: if held_out_popularity > 0f64 {
+      average_popularity / held_out_popularity
+    } else {
+      0f64
+    },
+    n_queries,
+    n_failed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eval::tests::{Fixed, assert_close};
+
+  fn held_out() -> Vec<HeldOut<u32, u32>> {
+    [(1, 10), (2, 20), (3, 30), (4, 10), (5, 99)].into_iter()
+      .map(|(subject, item)| HeldOut::new(subject, item))
+      .collect()
+  }
+
+  fn recommender() -> Fixed {
+    // the held-out item ranks 1st, 2nd, nowhere and 3rd, and subject 5 fails
+    Fixed::new(&[
+      (1, &[10, 11, 12]),
+      (2, &[21, 20, 22]),
+      (3, &[31, 32, 33]),
+      (4, &[11, 12, 10])
+    ])
+  }
+
+  #[test]
+  fn ranking_metrics_use_one_based_ranks() {
+    let report = evaluate_ranking(&recommender(), &held_out(), &[3, 1], Some(18));
+    assert_eq!((report.n_queries, report.n_failed), (4, 1));
+    assert_eq!(report.ranking.iter().map(|at| at.k).collect::<Vec<_>>(), vec![1, 3]);
+    assert_close(report.ranking[0].hit_rate, 0.25);
+    assert_close(report.ranking[0].ndcg, 0.25);
+    assert_close(report.ranking[1].hit_rate, 0.75);
+    assert_close(report.ranking[1].ndcg, (1.0 + 1.0 / 3f64.log2() + 0.5) / 4.0);
+    assert_close(report.mrr, (1.0 + 1.0 / 2.0 + 1.0 / 3.0) / 4.0);
+  }
+
+  #[test]
+  fn coverage_and_popularity() {
+    let report = evaluate_ranking(&recommender(), &held_out(), &[3], Some(18));
+    assert_eq!(report.n_recommended_items, 9);
+    assert_close(report.coverage.unwrap(), 0.5);
+    // item 10 is held out twice and recommended twice, item 20 once each
+    assert_close(report.average_popularity, 5.0 / 12.0);
+    // the answered pairs' items have popularities 2, 1, 1 and 2
+    assert_close(report.popularity_bias, (5.0 / 12.0) / 1.5);
+    assert!(evaluate_ranking(&recommender(), &held_out(), &[3], None).coverage.is_none());
+    assert!(evaluate_ranking(&recommender(), &held_out(), &[3], Some(0)).coverage.is_none());
+  }
+
+  #[test]
+  fn held_out_pairs_are_read_from_csv_or_tsv() {
+    let input = "# subject,item\n1,10\n\n2\t20\n 3 , 30 \n";
+    let pairs = read_held_out::<u32, u32, _>(input.as_bytes()).unwrap();
+    assert_eq!(pairs, vec![HeldOut::new(1, 10), HeldOut::new(2, 20), HeldOut::new(3, 30)]);
+  }
+
+  #[test]
+  fn held_out_errors_name_the_line() {
+    let error = |input: &str| match read_held_out::<u32, u32, _>(input.as_bytes()) {
+      Err(EvalError::Parse { line, message }) => (line, message),
+      other => panic!("expected a parse error, got {:?}", other)
+    };
+    assert_eq!(error("1,10\n2\n"), (2, "expected a subject and an item".to_string()));
+    assert_eq!(error("x,10\n"), (1, "invalid subject".to_string()));
+    assert_eq!(error("# header\n1,y\n"), (2, "invalid item".to_string()));
+  }
+}
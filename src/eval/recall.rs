@@ -0,0 +1,147 @@
+use std::{
+  collections::HashSet,
+  hash::Hash,
+  time::{Duration, Instant}
+};
+
+use serde::Serialize;
+use tracing::{Level, span, debug, warn};
+
+use super::{LatencySummary, mean};
+use crate::{
+  Recommender,
+  RecommendationList,
+  RecommendError
+};
+
+/// How well a candidate recommender's top `k` agree with a reference's.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecallAtK {
+  pub k: u16,
+  /// the mean share of the reference's top `k` the candidate also returned
+  pub recall: f64,
+  /// the mean number of items both top `k` lists contain
+  pub mean_overlap: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecallReport {
+  /// one entry per requested `k`, in ascending order
+  pub recall: Vec<RecallAtK>,
+  pub candidate_latency: LatencySummary,
+  pub reference_latency: LatencySummary,
+  /// the number of queries both recommenders answered
+  pub n_queries: usize,
+  /// the number of queries skipped because either recommender failed
+  pub n_failed: usize
+}
+
+/// Measure how much of `reference`'s results `candidate` recovers, e.g. an
+/// approximate backend against a `BruteForceRecommender` over the same
+/// vectors. Each recommender is asked once per query for the largest `k`,
+/// and the smaller `k` are measured on the top of those lists. Queries with
+/// an empty reference list don't count towards recall.
+pub fn evaluate_recall<K, R, C, F>(candidate: &C, reference: &F, queries: &[K], ks: &[u16])
+    -> RecallReport
+  where C: Recommender<K, R> + ?Sized,
+        F: Recommender<K, R> + ?Sized,
+        R: Eq + Hash {
+  let span = span!(Level::DEBUG, "evaluate-recall");
+  let _guard = span.enter();
+  let mut ks = ks.to_vec();
+  ks.sort_unstable();
+  ks.dedup();
+  let max_k = ks.last().copied().unwrap_or(0);
+  debug!("Evaluating recall@{:?} over {} queries", ks, queries.len());
+  let mut candidate_samples = Vec::with_capacity(queries.len());
+  let mut reference_samples = Vec::with_capacity(queries.len());
+  let mut recall_sums = vec![0f64; ks.len()];
+  let mut overlap_sums = vec![0f64; ks.len()];
+  let mut n_recall_queries = vec![0usize; ks.len()];
+  let mut n_failed = 0;
+  for query in queries {
+    let (candidate_result, candidate_time) = timed(|| candidate.recommend(query, max_k));
+    let (reference_result, reference_time) = timed(|| reference.recommend(query, max_k));
+    candidate_samples.push(candidate_time);
+    reference_samples.push(reference_time);
+    let (RecommendationList(found), RecommendationList(expected)) =
+      match (candidate_result, reference_result) {
+        (Ok(found), Ok(expected)) => (found, expected),
+        (Err(e), _) | (_, Err(e)) => {
+          warn!("Skipping query: {}", e);
+          n_failed += 1;
+          continue
+        }
+      };
+    for (i, k) in ks.iter().enumerate() {
+      let expected: HashSet<&R> = expected.iter()
+        .take(*k as usize)
+        .map(|rec| &rec.item_id)
+        .collect();
+      // a candidate repeating an item only recovers it once
+      let overlap = found.iter()
+        .take(*k as usize)
+        .map(|rec| &rec.item_id)
+        .filter(|item_id| expected.contains(item_id))
+        .collect::<HashSet<_>>()
+        .len();
+      overlap_sums[i] += overlap as f64;
+      if !expected.is_empty() {
+        recall_sums[i] += overlap as f64 / expected.len() as f64;
+        n_recall_queries[i] += 1;
+      }
+    }
+  }
+  let n_queries = queries.len() - n_failed;
+  let recall = ks.into_iter()
+    .enumerate()
+    .map(|(i, k)| RecallAtK {
+      k,
+      recall: mean(recall_sums[i], n_recall_queries[i]),
+      mean_overlap: mean(overlap_sums[i], n_queries)
+    })
+    .collect();
+  RecallReport {
+    recall,
+    candidate_latency: LatencySummary::from_samples(candidate_samples),
+    reference_latency: LatencySummary::from_samples(reference_samples),
+    n_queries,
+    n_failed
+  }
+}
+
+fn timed<T>(f: impl FnOnce() -> Result<T, RecommendError>) -> (Result<T, RecommendError>, Duration) {
+  let start = Instant::now();
+  let result = f();
+  (result, start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eval::tests::{Fixed, assert_close};
+
+  #[test]
+  fn recall_compares_top_k() {
+    let reference = Fixed::new(&[(1, &[10, 11, 12]), (2, &[20, 21, 22])]);
+    // query 2's candidate repeats a hit, which counts once
+    let candidate = Fixed::new(&[(1, &[10, 12, 13]), (2, &[23, 20, 20]), (3, &[30])]);
+    let report = evaluate_recall(&candidate, &reference, &[1, 2, 3], &[3, 1]);
+    assert_eq!((report.n_queries, report.n_failed), (2, 1));
+    assert_eq!(report.recall.iter().map(|at| at.k).collect::<Vec<_>>(), vec![1, 3]);
+    assert_close(report.recall[0].recall, 0.5);
+    assert_close(report.recall[0].mean_overlap, 0.5);
+    assert_close(report.recall[1].recall, (2.0 / 3.0 + 1.0 / 3.0) / 2.0);
+    assert_close(report.recall[1].mean_overlap, 1.5);
+  }
+
+  #[test]
+  fn empty_reference_lists_dont_count_towards_recall() {
+    let reference = Fixed::new(&[(1, &[10]), (2, &[])]);
+    let candidate = Fixed::new(&[(1, &[10]), (2, &[20])]);
+    let report = evaluate_recall(&candidate, &reference, &[1, 2], &[1]);
+    assert_eq!(report.n_queries, 2);
+    assert_close(report.recall[0].recall, 1.0);
+    assert_close(report.recall[0].mean_overlap, 0.5);
+  }
+}
@@ -0,0 +1,103 @@
+use std::{
+  collections::HashSet,
+  fmt,
+  hash::Hash,
+  sync::Arc
+};
+
+use super::{
+  Recommendation,
+  RecommendError,
+  RecommendationList
+};
+
+/// How many more candidates than requested the first round of [`overfetch`]
+/// asks for.
+const OVERFETCH_FACTOR: u16 = 2;
+
+/// Restricts which items a recommender may return, e.g. to leave out items
+/// a user already bought or that are out of stock.
+pub enum CandidateFilter<K> {
+  /// Only these items may be recommended.
+  Allow(HashSet<K>),
+  /// These items are never recommended.
+  Deny(HashSet<K>),
+  /// Only items the predicate returns `true` for may be recommended.
+  Predicate(Arc<dyn Fn(&K) -> bool + Send + Sync>)
+}
+
+impl<K> CandidateFilter<K>
+  where K: Eq + Hash {
+  pub fn allow<I>(items: I) -> Self
+    where I: IntoIterator<Item = K> {
+    CandidateFilter::Allow(items.into_iter().collect())
+  }
+
+  pub fn deny<I>(items: I) -> Self
+    where I: IntoIterator<Item = K> {
+    CandidateFilter::Deny(items.into_iter().collect())
+  }
+
+  pub fn predicate<F>(predicate: F) -> Self
+    where F: Fn(&K) -> bool + Send + Sync + 'static {
+    CandidateFilter::Predicate(Arc::new(predicate))
+  }
+
+  /// Whether `item` may be recommended.
+  pub fn accepts(&self, item: &K) -> bool {
+    match self {
+      CandidateFilter::Allow(items) => items.contains(item),
+      CandidateFilter::Deny(items) => !items.contains(item),
+      CandidateFilter::Predicate(predicate) => predicate(item)
+    }
+  }
+}
+
+impl<K> Clone for CandidateFilter<K>
+  where K: Clone {
+  fn clone(&self) -> Self {
+    match self {
+      CandidateFilter::Allow(items) => CandidateFilter::Allow(items.clone()),
+      CandidateFilter::Deny(items) => CandidateFilter::Deny(items.clone()),
+      CandidateFilter::Predicate(predicate) => CandidateFilter::Predicate(predicate.clone())
+    }
+  }
+}
+
+impl<K> fmt::Debug for CandidateFilter<K>
+  where K: fmt::Debug {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CandidateFilter::Allow(items) => f.debug_tuple("Allow").field(items).finish(),
+      CandidateFilter::Deny(items) => f.debug_tuple("Deny").field(items).finish(),
+      CandidateFilter::Predicate(_) => f.write_str("Predicate(..)")
+    }
+  }
+}
+
+/// Collect up to `n_items` recommendations that `accept` lets through from
+/// a source that can't filter while searching. `fetch` is asked for the top
+/// `n` recommendations with increasingly large `n` until enough are
+/// accepted, asking for more stops producing more, or the scores fall
+/// below `min_score`.
+pub fn overfetch<R, A, F>(n_items: u16, min_score: Option<f32>, accept: A, mut fetch: F)
+    -> Result<Vec<Recommendation<R>>, RecommendError>
+  where A: Fn(&Recommendation<R>) -> bool,
+        F: FnMut(u16) -> Result<RecommendationList<R>, RecommendError> {
+  let mut n_fetch = n_items.saturating_mul(OVERFETCH_FACTOR).max(1);
+  let mut n_fetched = 0;
+  loop {
+    let RecommendationList(mut recs) = fetch(n_fetch)?;
+    let below_min_score = recs.last()
+      .zip(min_score)
+      .is_some_and(|(last, min_score)| last.score < min_score);
+    let exhausted = recs.len() <= n_fetched || n_fetch == u16::MAX || below_min_score;
+    n_fetched = recs.len();
+    recs.retain(&accept);
+    if recs.len() >= n_items as usize || exhausted {
+      recs.truncate(n_items as usize);
+      return Ok(recs)
+    }
+    n_fetch = n_fetch.saturating_mul(2);
+  }
+}
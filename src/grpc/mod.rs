@@ -0,0 +1,314 @@
+//! A gRPC service serving any recommender keyed by `u64`, and a client for
+//! it. The service is defined in `proto/recommender.proto`, and its messages,
+//! client and server are generated from it by `build.rs`.
+
+pub mod pb;
+
+use std::{
+  collections::HashSet,
+  sync::Arc
+};
+
+use tonic::{
+  Code,
+  Request,
+  Response,
+  Status,
+  async_trait,
+  transport::Channel
+};
+use tokio::task;
+use tracing::{Level, span, debug};
+
+use super::{
+  CandidateFilter,
+  DynRecommender,
+  RecommendRequest,
+  Recommendation,
+  RecommendationList,
+  RecommendError,
+  Subject
+};
+
+use pb::{
+  batch_result,
+  filter,
+  recommender_server::{Recommender as RecommenderRpc, RecommenderServer}
+};
+
+/// A client of the `recommender.v1.Recommender` service, e.g. for testing
+/// against a local [`RecommenderService`]. Connect to a server with
+/// [`RecommenderClient::connect`], such as `http://127.0.0.1:50051`.
+pub type RecommenderClient = pb::recommender_client::RecommenderClient<Channel>;
+
+/// The number of recommendations returned when a request leaves `n_items`
+/// at 0.
+const DEFAULT_N_ITEMS: u16 = 10;
+
+/// Serves a recommender as the `recommender.v1.Recommender` gRPC service.
+/// Add it to a [`tonic::transport::Server`] with
+/// `add_service(service.into_server())`.
+///
+/// Each call runs on tokio's blocking pool with its own handle on the
+/// service, so concurrent RPCs search in parallel.
+#[derive(Clone)]
+pub struct RecommenderService {
+  recommender: Arc<DynRecommender<u64, u64>>
+}
+
+impl RecommenderService {
+  pub fn new(recommender: Box<DynRecommender<u64, u64>>) -> Self {
+    RecommenderService { recommender: Arc::from(recommender) }
+  }
+
+  /// The generated server for this service.
+  pub fn into_server(self) -> RecommenderServer<Self> {
+    RecommenderServer::new(self)
+  }
+
+  fn recommend(&self, request: pb::RecommendRequest) -> Result<pb::RecommendResponse, Status> {
+    let options = Options::try_from(request.options.unwrap_or_default())?;
+    let recs = self.recommender.recommend_with(&options.request(Subject::Item(&request.item_id)))?;
+    Ok(recs.into())
+  }
+
+  fn recommend_batch(&self, request: pb::RecommendBatchRequest)
+      -> Result<pb::RecommendBatchResponse, Status> {
+    let span = span!(Level::DEBUG, "grpc-recommend-batch");
+    let _guard = span.enter();
+    debug!("Recommending for {} items", request.item_ids.len());
+    let options = Options::try_from(request.options.unwrap_or_default())?;
+    let results = match options.is_plain() {
+      true => self.recommender.recommend_batch(&request.item_ids, options.n_items),
+      false => request.item_ids.iter()
+        .map(|item_id| self.recommender.recommend_with(&options.request(Subject::Item(item_id))))
+        .collect()
+    };
+    let results = results.into_iter()
+      .map(|result| pb::BatchResult {
+        result: Some(match result {
+          Ok(recs) => batch_result::Result::Recommendations(recs.into()),
+          Err(e) => {
+            let status = Status::from(e);
+            batch_result::Result::Error(pb::Error {
+              code: status.code() as i32,
+              message: status.message().to_owned()
+            })
+          }
+        })
+      })
+      .collect();
+    Ok(pb::RecommendBatchResponse { results })
+  }
+
+  fn recommend_by_vector(&self, request: pb::RecommendByVectorRequest)
+      -> Result<pb::RecommendResponse, Status> {
+    let options = Options::try_from(request.options.unwrap_or_default())?;
+    let recs = self.recommender.recommend_with(&options.request(Subject::Vector(&request.vector)))?;
+    Ok(recs.into())
+  }
+
+  /// Run one of the service's methods on the blocking thread pool.
+  async fn spawn<Req, Res>(&self, call: fn(&Self, Req) -> Result<Res, Status>, request: Request<Req>)
+      -> Result<Response<Res>, Status>
+    where Req: Send + 'static,
+          Res: Send + 'static {
+    let service = self.clone();
+    task::spawn_blocking(move || call(&service, request.into_inner())).await
+      .map_err(|e| Status::internal(e.to_string()))?
+      .map(Response::new)
+  }
+}
+
+#[async_trait]
+impl RecommenderRpc for RecommenderService {
+  async fn recommend(&self, request: Request<pb::RecommendRequest>)
+      -> Result<Response<pb::RecommendResponse>, Status> {
+    self.spawn(RecommenderService::recommend, request).await
+  }
+
+  async fn recommend_batch(&self, request: Request<pb::RecommendBatchRequest>)
+      -> Result<Response<pb::RecommendBatchResponse>, Status> {
+    self.spawn(RecommenderService::recommend_batch, request).await
+  }
+
+  async fn recommend_by_vector(&self, request: Request<pb::RecommendByVectorRequest>)
+      -> Result<Response<pb::RecommendResponse>, Status> {
+    self.spawn(RecommenderService::recommend_by_vector, request).await
+  }
+}
+
+/// [`pb::RecommendOptions`] checked and converted, for building requests to
+/// borrow from.
+struct Options {
+  n_items: u16,
+  offset: u16,
+  filter: Option<CandidateFilter<u64>>,
+  exclude: HashSet<u64>,
+  min_score: Option<f32>
+}
+
+impl TryFrom<pb::RecommendOptions> for Options {
+  type Error = Status;
+
+  fn try_from(options: pb::RecommendOptions) -> Result<Self, Status> {
+    let n_items = match options.n_items {
+      0 => DEFAULT_N_ITEMS,
+      n => u16::try_from(n)
+        .map_err(|_| Status::invalid_argument(format!("n_items can be at most {}", u16::MAX)))?
+    };
+    let offset = u16::try_from(options.offset)
+      .map_err(|_| Status::invalid_argument(format!("offset can be at most {}", u16::MAX)))?;
+    let filter = options.filter
+      .and_then(|filter| filter.kind)
+      .map(|kind| match kind {
+        filter::Kind::Allow(set) => CandidateFilter::allow(set.items),
+        filter::Kind::Deny(set) => CandidateFilter::deny(set.items)
+      });
+    Ok(Options {
+      n_items,
+      offset,
+      filter,
+      exclude: options.exclude.into_iter().collect(),
+      min_score: options.min_score
+    })
+  }
+}
+
+impl Options {
+  /// Whether the options only set `n_items`, so a batch can be answered by
+  /// [`Recommender::recommend_batch`](super::Recommender::recommend_batch).
+  fn is_plain(&self) -> bool {
+    self.offset == 0 && self.filter.is_none() && self.exclude.is_empty() && self.min_score.is_none()
+  }
+
+  fn request<'a>(&'a self, subject: Subject<'a, u64>) -> RecommendRequest<'a, u64, u64> {
+    let mut request = RecommendRequest::new(subject, self.n_items)
+      .with_offset(self.offset)
+      .with_exclude(&self.exclude);
+    if let Some(filter) = &self.filter {
+      request = request.with_filter(filter);
+    }
+    if let Some(min_score) = self.min_score {
+      request = request.with_min_score(min_score);
+    }
+    request
+  }
+}
+
+impl From<RecommendError> for Status {
+  fn from(e: RecommendError) -> Self {
+    let code = match e {
+      RecommendError::NotFound => Code::NotFound,
+      RecommendError::IncompatibleId
+        | RecommendError::DimensionMismatch { .. } => Code::InvalidArgument,
+      RecommendError::UnsupportedRequest => Code::Unimplemented,
+      #[allow(unreachable_patterns)]
+      _ => Code::Internal
+    };
+    Status::new(code, e.to_string())
+  }
+}
+
+impl From<RecommendationList<u64>> for pb::RecommendResponse {
+  fn from(list: RecommendationList<u64>) -> Self {
+    pb::RecommendResponse {
+      recommendations: list.0.into_iter()
+        .map(|rec| pb::Recommendation { item_id: rec.item_id, score: rec.score })
+        .collect()
+    }
+  }
+}
+
+impl From<pb::RecommendResponse> for RecommendationList<u64> {
+  fn from(response: pb::RecommendResponse) -> Self {
+    RecommendationList(response.recommendations.into_iter()
+      .map(|rec| Recommendation::new(rec.item_id, rec.score))
+      .collect())
+  }
+}
+
+impl pb::BatchResult {
+  /// The recommendations for the item, or why there are none.
+  pub fn into_result(self) -> Result<RecommendationList<u64>, Status> {
+    match self.result {
+      Some(batch_result::Result::Recommendations(response)) => Ok(response.into()),
+      Some(batch_result::Result::Error(e)) => Err(Status::new(Code::from(e.code), e.message)),
+      None => Err(Status::internal("empty batch result"))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::SocketAddr;
+
+  use tonic::transport::{Server, server::TcpIncoming};
+
+  use super::*;
+  use crate::Recommender;
+
+  /// Knows items 1 and 2.
+  struct Fixed;
+
+  impl Recommender<u64, u64> for Fixed {
+    fn recommend(&self, item_id: &u64, n_items: u16)
+        -> Result<RecommendationList<u64>, RecommendError> {
+      match item_id {
+        1 | 2 => Ok(RecommendationList((10..13)
+          .map(|item| Recommendation::new(item + item_id, 1f32 / item as f32))
+          .take(n_items as usize)
+          .collect())),
+        _ => Err(RecommendError::NotFound)
+      }
+    }
+  }
+
+  async fn client() -> RecommenderClient {
+    let incoming = TcpIncoming::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let addr = incoming.local_addr().unwrap();
+    let service = RecommenderService::new(Box::new(Fixed));
+    tokio::spawn(Server::builder()
+      .add_service(service.into_server())
+      .serve_with_incoming(incoming));
+    RecommenderClient::connect(format!("http://{}", addr)).await.unwrap()
+  }
+
+  fn options(n_items: u32) -> Option<pb::RecommendOptions> {
+    Some(pb::RecommendOptions { n_items, ..Default::default() })
+  }
+
+  fn item_ids(recs: RecommendationList<u64>) -> Vec<u64> {
+    recs.0.iter().map(|rec| rec.item_id).collect()
+  }
+
+  #[tokio::test]
+  async fn recommends_over_the_network() {
+    let mut client = client().await;
+    let request = pb::RecommendRequest { item_id: 1, options: options(2) };
+    let response = client.recommend(request).await.unwrap().into_inner();
+    assert_eq!(item_ids(response.into()), vec![11, 12]);
+    let request = pb::RecommendRequest { item_id: 1, options: None };
+    let response = client.recommend(request).await.unwrap().into_inner();
+    assert_eq!(response.recommendations.len(), 3);
+  }
+
+  #[tokio::test]
+  async fn batches_answer_each_item() {
+    let mut client = client().await;
+    let request = pb::RecommendBatchRequest { item_ids: vec![2, 9, 1], options: options(1) };
+    let results = client.recommend_batch(request).await.unwrap().into_inner().results;
+    let mut results = results.into_iter().map(pb::BatchResult::into_result);
+    assert_eq!(item_ids(results.next().unwrap().unwrap()), vec![12]);
+    assert_eq!(results.next().unwrap().unwrap_err().code(), Code::NotFound);
+    assert_eq!(item_ids(results.next().unwrap().unwrap()), vec![11]);
+    assert!(results.next().is_none());
+  }
+
+  #[tokio::test]
+  async fn unknown_items_are_not_found() {
+    let mut client = client().await;
+    let request = pb::RecommendRequest { item_id: 9, options: None };
+    assert_eq!(client.recommend(request).await.unwrap_err().code(), Code::NotFound);
+  }
+}
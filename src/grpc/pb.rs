@@ -0,0 +1,4 @@
+//! Messages, client and server of `proto/recommender.proto`, package
+//! `recommender.v1`, generated by `build.rs`.
+
+tonic::include_proto!("recommender.v1");
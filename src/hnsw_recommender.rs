@@ -1,7 +1,21 @@
-use std::hash::Hash;
+use std::{
+  collections::HashSet,
+  fs::{self, File},
+  hash::Hash,
+  fmt::Display,
+  io::{BufReader, BufWriter, Read, Write},
+  path::Path,
+  ptr::NonNull,
+  sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard}
+};
 
 use dashmap::DashMap;
-use hnsw_rs::hnsw::{Hnsw, Neighbour};
+use hnsw_rs::{
+  api::AnnT,
+  hnsw::Hnsw,
+  hnswio::HnswIo
+};
+use hnsw_rs::filter::FilterT;
 use ndarray::{
   parallel::prelude::{
     ParallelIterator,
@@ -9,113 +23,355 @@ use ndarray::{
     IntoParallelIterator
   },
   prelude::{
-    Array1,
     Array2,
-    Axis,
-    s
+    ArrayView1,
+    Axis
   },
 };
 use tap::Tap;
+use thiserror::Error;
 use tracing::{Level, span, debug, trace};
 
 use super::{
-  Recommendation,
+  CandidateFilter,
+  RecommendRequest,
   Recommender,
   RecommendError,
   RecommendationList,
-  VectorProvider
+  Subject,
+  VectorProvider,
+  VectorRecommender
 };
 
-#[cfg(feature = "space")]
-use std::{
-  iter::Map,
-  vec::IntoIter
+use super::score::{
+  DistanceTransform,
+  DotTransform,
+  Metric,
+  ScoreNormalizer,
+  ScoredDistance
 };
-#[cfg(feature = "space")]
-use super::spatial::{Distance, NavigableIndex};
+use super::spatial::{
+  Distance,
+  NavigableIndex,
+  rank_item_neighbors,
+  rank_neighbors,
+  recommend_from_index
+};
+
+/// Minimum search width used on the bottom layer of the graph when no
+/// `ef_search` is configured.
+const DEFAULT_EF_SEARCH: usize = 64;
+
+/// Version of the layout written by [`HnswRecommender::save`]. Bump this
+/// whenever the dump format changes so stale dumps are rejected on load.
+pub const DUMP_FORMAT_VERSION: u32 = 3;
+const DUMP_MAGIC: &[u8; 8] = b"HNSWREC\0";
+/// basename of the hnsw_rs graph and data files within a dump directory
+const GRAPH_BASENAME: &str = "index";
+/// name of the file holding the query settings and the keyed vector cache
+/// within a dump directory
+const VECTOR_CACHE_FILE: &str = "vectors.bin";
+/// the length of the magic, format version and query settings preceding the
+/// vector cache
+const DUMP_HEADER_LEN: u64 = 8 + 4 + 8 + 5 + 5 + 1;
+/// the length of the row count and dimensions preceding the cached rows
+const CACHE_HEADER_LEN: u64 = 8 + 8;
+/// hnsw_rs only dumps graphs with its maximum number of layers, and caps
+/// larger layer counts to it
+pub const DUMP_N_LAYERS: usize = 16;
 
 pub use hnsw_rs::dist;
 pub use hnsw_rs::dist::Distance as HnswDistance;
 
 pub struct HnswRecommender<'a, D>
   where D: HnswDistance<f32> + Send + Sync {
-  index: Hnsw<'a, f32, D>,
-  vector_cache: KeyedVectorCache<usize>
+  /// hnsw_rs doesn't support inserting while searching, so insertions take
+  /// the write lock
+  index: RwLock<Hnsw<'a, f32, D>>,
+  vector_cache: KeyedVectorCache<usize>,
+  ef_search: Option<usize>,
+  score_normalizer: ScoreNormalizer,
+  min_score: Option<f32>,
+  /// what a reloaded graph borrows from. Fields are dropped in declaration
+  /// order, so it outlives the graph.
+  loader: Option<GraphLoader>
+}
+
+/// Owns the [`HnswIo`] a graph was reloaded with, since hnsw_rs ties the
+/// graph's lifetime to it. It's only reachable through a raw pointer, so
+/// moving the recommender doesn't invalidate the graph's borrow of it.
+struct GraphLoader(NonNull<HnswIo>);
+
+// SAFETY: `GraphLoader` owns its `HnswIo` like a `Box` would, and `HnswIo`
+// is `Send` and `Sync`.
+unsafe impl Send for GraphLoader {}
+unsafe impl Sync for GraphLoader {}
+const _: () = super::assert_send_sync::<HnswIo>();
+
+impl GraphLoader {
+  fn new(loader: HnswIo) -> Self {
+    GraphLoader(NonNull::from(Box::leak(Box::new(loader))))
+  }
+
+  /// # Safety
+  ///
+  /// Whatever borrows the loader must be dropped before the `GraphLoader`.
+  unsafe fn get(&self) -> &'static HnswIo {
+    self.0.as_ref()
+  }
+}
+
+impl Drop for GraphLoader {
+  fn drop(&mut self) {
+    // SAFETY: the pointer came from `Box::leak` in `GraphLoader::new` and
+    // nothing borrowing it outlives `self`.
+    drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+  }
 }
 
 impl<'a, D> HnswRecommender<'a, D>
   where D: HnswDistance<f32> + Send + Sync {
   fn new(index: Hnsw<'a, f32, D>, vector_cache: KeyedVectorCache<usize>) -> Self {
-    Self { index, vector_cache }
+    Self {
+      index: RwLock::new(index),
+      vector_cache,
+      ef_search: None,
+      score_normalizer: ScoreNormalizer::default(),
+      min_score: None,
+      loader: None
+    }
+  }
+
+  /// Set how distances are turned into recommendation scores.
+  pub fn with_score_normalizer(mut self, score_normalizer: ScoreNormalizer) -> Self {
+    self.score_normalizer = score_normalizer;
+    self
+  }
+
+  /// Drop recommendations scoring below `min_score` from every request, see
+  /// [`RecommendRequest::min_score`].
+  pub fn with_min_score(mut self, min_score: Option<f32>) -> Self {
+    self.min_score = min_score;
+    self
+  }
+
+  /// Set the search width used on the bottom layer of the graph. Larger
+  /// values improve recall at the cost of latency. It is never lower than
+  /// the number of items requested.
+  pub fn with_ef_search(mut self, ef_search: Option<usize>) -> Self {
+    self.ef_search = ef_search;
+    self
+  }
+
+  /// The search width for a query of `n_items`, preferring `ef_search` over
+  /// the recommender's default.
+  fn effective_ef_search(&self, n_items: u16, ef_search: Option<usize>) -> usize {
+    ef_search.or(self.ef_search)
+      .unwrap_or(DEFAULT_EF_SEARCH)
+      .max(n_items as usize)
   }
 
   pub fn builder<P>() -> HnswRecommenderBuilder<P, D>
     where P: VectorProvider<usize>{
     HnswRecommenderBuilder::default()
   }
+
+  /// The dimensionality of the vectors stored in the index.
+  pub fn vector_dimensions(&self) -> usize {
+    self.vector_cache.dimensions()
+  }
+
+  fn check_dimensions(&self, vector: &[f32]) -> Result<(), RecommendError> {
+    if vector.len() != self.vector_dimensions() {
+      return Err(RecommendError::DimensionMismatch {
+        expected: self.vector_dimensions(),
+        received: vector.len()
+      })
+    }
+    Ok(())
+  }
+
+  /// The number of keys that can be recommended, excluding deleted ones.
+  pub fn n_items(&self) -> usize {
+    self.vector_cache.id_to_vector.len()
+  }
+
+  /// Insert a vector into the live index. If `key` is already indexed its
+  /// vector is replaced: the old graph point is soft-deleted and a new one
+  /// is inserted.
+  pub fn insert(&self, key: usize, vector: Vec<f32>) -> Result<(), RecommendError> {
+    let span = span!(Level::DEBUG, "hnsw-insert");
+    let _guard = span.enter();
+    self.check_dimensions(&vector)?;
+    trace!("Inserting key \"{}\" into the index", key);
+    let row = self.vector_cache.push(key, &vector);
+    write_lock(&self.index).insert_slice((&vector, row));
+    Ok(())
+  }
+
+  /// Soft-delete `key`: it's excluded from results and can no longer be
+  /// used as a subject, but its graph point is kept to preserve
+  /// connectivity. Returns whether the key was indexed.
+  pub fn delete(&self, key: usize) -> bool {
+    trace!("Deleting key \"{}\"", key);
+    self.vector_cache.remove(&key)
+  }
+
+  /// Search for the nearest live points to `subject`. Keys rejected by
+  /// `accept` are skipped during the search, widening it if too few
+  /// accepted points were found.
+  fn search_neighbors(
+    &self, subject: &[f32], n_items: u16, ef_search: Option<usize>,
+    accept: Option<&dyn Fn(&usize) -> bool>
+  ) -> Vec<Distance<usize>> {
+    let mut ef_search = self.effective_ef_search(n_items, ef_search);
+    let index = read_lock(&self.index);
+    let store = read_lock(&self.vector_cache.store);
+    let n_rows = store.keys.len();
+    let filter = |row: &usize| {
+      store.live[*row] && accept.is_none_or(|accept| accept(&store.keys[*row]))
+    };
+    // Filtering slows the search down, so only pay for it once rows have
+    // actually been deleted or replaced, or the caller asked for it
+    let filter = (store.n_dead > 0 || accept.is_some()).then_some(&filter as &dyn FilterT);
+    let results = loop {
+      trace!("Searching for point in index with ef_search {}", ef_search);
+      let results = index.search_filter(subject, n_items as usize, ef_search, filter);
+      if accept.is_none() || results.len() >= n_items as usize || ef_search >= n_rows {
+        break results
+      }
+      ef_search = ef_search.saturating_mul(2).min(n_rows);
+    };
+    results
+      .tap(|results| trace!("Searched returned {} results", results.len()))
+      .into_iter()
+      .map(|neighbor| Distance::new(store.keys[neighbor.d_id], neighbor.distance))
+      .collect()
+  }
 }
 
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+  lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+  lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Vectors keyed by ID. The rows double as the point IDs in the graph, so
+/// replacing or deleting a key leaves a dead row behind rather than
+/// shifting the other rows.
 pub(crate) struct KeyedVectorCache<K> {
+  /// maps each live key to its row in the store
   id_to_vector: DashMap<K, usize>,
-  vectors: Array2<f32>
+  store: RwLock<VectorStore<K>>
+}
+
+struct VectorStore<K> {
+  vectors: Array2<f32>,
+  /// the key each row was inserted under
+  keys: Vec<K>,
+  /// whether each row still belongs to a live key
+  live: Vec<bool>,
+  /// the number of rows that are no longer live
+  n_dead: usize
 }
 
 impl<K> KeyedVectorCache<K>
-  where K: Eq + Hash {
-  pub fn new(id_to_vector: DashMap<K, usize>, vectors: Array2<f32>) -> Self {
-    Self { id_to_vector, vectors }
+  where K: Eq + Hash + Clone {
+  fn new(vectors: Array2<f32>, keys: Vec<K>, live: Vec<bool>) -> Self {
+    let id_to_vector = keys.iter()
+      .zip(live.iter())
+      .enumerate()
+      .filter(|(_, (_, live))| **live)
+      .map(|(row, (key, _))| (key.clone(), row))
+      .collect();
+    let n_dead = live.iter().filter(|live| !**live).count();
+    Self {
+      id_to_vector,
+      store: RwLock::new(VectorStore { vectors, keys, live, n_dead })
+    }
   }
 
   pub fn get_vector(&self, key: &K) -> Option<Vec<f32>> {
-    self.id_to_vector.get(key)
-      .map(|val| self.vectors.slice(s!(*val, ..)))
-      // TODO
-      .map(|arr| arr.into_owned())
-      .map(|owned| owned.into_raw_vec())
+    let row = *self.id_to_vector.get(key)?;
+    Some(read_lock(&self.store).vectors.row(row).to_vec())
   }
 
-  fn for_provider<P>(provider: P) -> KeyedVectorCacheInitContext<K>
+  fn dimensions(&self) -> usize {
+    read_lock(&self.store).vectors.dim().1
+  }
+
+  /// Append `vector` as a new row for `key`, retiring the key's previous
+  /// row if it had one. Returns the new row.
+  fn push(&self, key: K, vector: &[f32]) -> usize {
+    let mut store = write_lock(&self.store);
+    store.vectors.push_row(ArrayView1::from(vector))
+      .expect("vector dimensions are checked before insertion");
+    store.keys.push(key.clone());
+    store.live.push(true);
+    let row = store.keys.len() - 1;
+    if let Some(previous) = self.id_to_vector.insert(key, row) {
+      store.retire(previous);
+    }
+    row
+  }
+
+  /// Retire the row of `key`. Returns whether the key was present.
+  fn remove(&self, key: &K) -> bool {
+    let mut store = write_lock(&self.store);
+    self.id_to_vector.remove(key)
+      .map(|(_, row)| store.retire(row))
+      .is_some()
+  }
+
+  fn for_provider<P>(provider: P) -> Result<Self, HnswRecommenderBuilderError>
       where P: VectorProvider<K>,
-            K: Eq + Hash + Clone {
+            K: Display {
     let span  = span!(Level::DEBUG, "keyed-vector-cache-init");
     let _guard = span.enter();
-    let shape = (provider.len(), provider.vector_dimensions() as usize);
-    let mut uninitialized = Array2::<f32>::uninit(shape);
-    let index_mapping = DashMap::<K, usize>::with_capacity(provider.len());
-    let mut order = Vec::<K>::with_capacity(provider.len());
+    let dims = provider.vector_dimensions() as usize;
+    // Providers may only know a lower bound of their length, so storage
+    // grows past it as needed
+    let (n_expected, _) = provider.size_hint();
+    let mut values = Vec::<f32>::with_capacity(n_expected * dims);
+    let mut keys = Vec::<K>::with_capacity(n_expected);
+    let mut seen = HashSet::<K>::with_capacity(n_expected);
     debug!("Pre-init: Consuming vector provider");
-    for (i, keyed_vector) in provider.enumerate() {
-      order.push(keyed_vector.key.clone());
-      index_mapping.insert(keyed_vector.key, i);
-      let as_array = Array1::from_vec(keyed_vector.vector);
-      as_array.move_into_uninit(&mut uninitialized.slice_mut(s!(i, ..)));
-    }
-    unsafe {
-      KeyedVectorCacheInitContext {
-       cache: Self::new(index_mapping, uninitialized.assume_init()),
-       insertion_order: order
+    for keyed_vector in provider {
+      if keyed_vector.vector.len() != dims {
+        return Err(HnswRecommenderBuilderError::ValidationError(format!(
+          "vector for key {} has {} dimensions, expected {}",
+          keyed_vector.key, keyed_vector.vector.len(), dims
+        )))
+      }
+      if !seen.insert(keyed_vector.key.clone()) {
+        return Err(HnswRecommenderBuilderError::ValidationError(
+          format!("duplicate key {}", keyed_vector.key)
+        ))
       }
+      keys.push(keyed_vector.key);
+      values.extend(keyed_vector.vector);
     }
+    let vectors = Array2::from_shape_vec((keys.len(), dims), values)
+      .map_err(|e| HnswRecommenderBuilderError::ValidationError(e.to_string()))?;
+    let live = vec![true; keys.len()];
+    Ok(Self::new(vectors, keys, live))
   }
 }
 
-struct KeyedVectorCacheInitContext<K> {
-  /// the newly constructed cache
-  cache: KeyedVectorCache<K>,
-  /// the order that the vectors were inserted into the map
-  insertion_order: Vec<K>
-}
-
-impl<K> From<KeyedVectorCacheInitContext<K>> for (KeyedVectorCache<K>, Vec<K>) {
-  fn from(value: KeyedVectorCacheInitContext<K>) -> Self {
-    (value.cache, value.insertion_order)
+impl<K> VectorStore<K> {
+  fn retire(&mut self, row: usize) {
+    if std::mem::replace(&mut self.live[row], false) {
+      self.n_dead += 1;
+    }
   }
 }
 
 impl<'a, T, D, Rec> Recommender<T, Rec> for HnswRecommender<'a, D>
-  where D: HnswDistance<f32> + Send + Sync,
+  where D: HnswDistance<f32> + ScoredDistance + Send + Sync,
         T: TryInto<usize> + Clone,
-        Rec: From<usize> + PartialEq + PartialEq<T> + PartialEq<usize> {
+        Rec: From<usize> + PartialEq {
   fn recommend(&self, item_id: &T, n_items: u16)
       -> Result<RecommendationList<Rec>, RecommendError> {
     let span = span!(Level::DEBUG, "hnsw-recommend");
@@ -123,17 +379,127 @@ impl<'a, T, D, Rec> Recommender<T, Rec> for HnswRecommender<'a, D>
     debug!("Converting ID to usize");
     let converted: usize = item_id.clone().try_into()
       .map_err(|_| RecommendError::IncompatibleId)?;
-    self.get_point(&converted)
-      .ok_or(RecommendError::NotFound)
-      .map(|point| {
-        self.search(&point, n_items)
-          .map(|distance| (distance.item_id, 1f32 - distance.distance))
-          .map(Recommendation::from)
+    recommend_from_index(self, &converted, n_items)
+  }
+
+  fn recommend_batch(&self, item_ids: &[T], n_items: u16)
+      -> Vec<Result<RecommendationList<Rec>, RecommendError>> {
+    let span = span!(Level::DEBUG, "hnsw-recommend-batch");
+    let _guard = span.enter();
+    debug!("Looking up {} subject vectors", item_ids.len());
+    let subjects: Vec<Result<(usize, Vec<f32>), RecommendError>> = item_ids.iter()
+      .map(|item_id| {
+        let converted: usize = item_id.clone().try_into()
+          .map_err(|_| RecommendError::IncompatibleId)?;
+        self.vector_cache.get_vector(&converted)
+          .map(|vector| (converted, vector))
+          .ok_or(RecommendError::NotFound)
+      })
+      .collect();
+    let queries: Vec<Vec<f32>> = subjects.iter()
+      .filter_map(|subject| subject.as_ref().ok())
+      .map(|(_, vector)| vector.clone())
+      .collect();
+    debug!("Searching index for {} subjects in parallel", queries.len());
+    let mut results = queries.into_par_iter()
+      .map(|query| self.search_neighbors(&query, n_items.saturating_add(1), None, None))
+      .collect::<Vec<_>>()
+      .into_iter();
+    subjects.into_iter()
+      .map(|subject| {
+        let (converted, _) = subject?;
+        let neighbors = results.next()
+          .expect("one search result per subject vector");
+        Ok(rank_item_neighbors(self, &converted, neighbors, n_items))
       })
-      .map(|neighbors| RecommendationList::new_with_subject(&converted, neighbors))
+      .collect()
+  }
+
+  fn recommend_with(&self, request: &RecommendRequest<T, Rec>)
+      -> Result<RecommendationList<Rec>, RecommendError>
+    where Rec: Eq + Hash {
+    let span = span!(Level::DEBUG, "hnsw-recommend-with");
+    let _guard = span.enter();
+    let stored;
+    let (subject_key, point) = match request.subject {
+      Subject::Item(item_id) => {
+        let converted: usize = item_id.clone().try_into()
+          .map_err(|_| RecommendError::IncompatibleId)?;
+        stored = self.vector_cache.get_vector(&converted)
+          .ok_or(RecommendError::NotFound)?;
+        (Some(converted), stored.as_slice())
+      },
+      Subject::Vector(vector) => {
+        self.check_dimensions(vector)?;
+        (None, vector)
+      }
+    };
+    Ok(self.recommend_for_point(subject_key, point, request))
   }
 }
 
+impl<'a, D> HnswRecommender<'a, D>
+  where D: HnswDistance<f32> + ScoredDistance + Send + Sync {
+  /// Like [`Recommender::recommend`], but searching with `ef_search`
+  /// instead of the recommender's default.
+  pub fn recommend_with_ef_search<T, Rec>(&self, item_id: &T, n_items: u16, ef_search: usize)
+      -> Result<RecommendationList<Rec>, RecommendError>
+    where T: TryInto<usize> + Clone,
+          Rec: From<usize> + PartialEq {
+    let span = span!(Level::DEBUG, "hnsw-recommend");
+    let _guard = span.enter();
+    let converted: usize = item_id.clone().try_into()
+      .map_err(|_| RecommendError::IncompatibleId)?;
+    let point = self.vector_cache.get_vector(&converted)
+      .ok_or(RecommendError::NotFound)?;
+    let neighbors = self.search_neighbors(
+      &point, n_items.saturating_add(1), Some(ef_search), None
+    );
+    Ok(rank_item_neighbors(self, &converted, neighbors, n_items))
+  }
+
+  /// Serve `request` from `point`, which is the vector of `subject_key` if
+  /// the subject is a stored item.
+  fn recommend_for_point<K, Rec>(
+    &self, subject_key: Option<usize>, point: &[f32], request: &RecommendRequest<K, Rec>
+  ) -> RecommendationList<Rec>
+    where Rec: From<usize> + Eq + Hash {
+    // Rejecting the subject during the search keeps it from taking up a slot
+    let accept = |key: &usize| Some(*key) != subject_key && request.accepts(&Rec::from(*key));
+    let accept = request.is_filtered().then_some(&accept as &dyn Fn(&usize) -> bool);
+    let n_search = request.n_ranked().saturating_add(subject_key.is_some() as u16);
+    let neighbors = self.search_neighbors(point, n_search, request.search.ef_search, accept);
+    request.finish(rank_neighbors(self, subject_key.as_ref(), neighbors))
+  }
+}
+
+impl<'a, D, Rec> VectorRecommender<Rec> for HnswRecommender<'a, D>
+  where D: HnswDistance<f32> + ScoredDistance + Send + Sync,
+        Rec: From<usize> + PartialEq {
+  fn recommend_by_vector(&self, vector: &[f32], n_items: u16)
+      -> Result<RecommendationList<Rec>, RecommendError> {
+    let span = span!(Level::DEBUG, "hnsw-recommend-by-vector");
+    let _guard = span.enter();
+    self.check_dimensions(vector)?;
+    let neighbors = self.search_neighbors(vector, n_items, None, None);
+    Ok(rank_neighbors(self, None, neighbors))
+  }
+
+  fn recommend_by_vector_filtered(&self, vector: &[f32], n_items: u16, filter: &CandidateFilter<Rec>)
+      -> Result<RecommendationList<Rec>, RecommendError>
+    where Rec: Eq + Hash {
+    let span = span!(Level::DEBUG, "hnsw-recommend-by-vector-filtered");
+    let _guard = span.enter();
+    self.check_dimensions(vector)?;
+    let request = RecommendRequest::<(), Rec>::vector(vector, n_items).with_filter(filter);
+    Ok(self.recommend_for_point(None, vector, &request))
+  }
+}
+
+/// Arguments for building an [`HnswRecommender`].
+///
+/// Builds are not reproducible: hnsw_rs assigns graph layers from an RNG
+/// that can't be seeded, and vectors are inserted in parallel.
 #[derive(Builder)]
 #[builder(name = "HnswRecommenderBuilder", pattern="owned", public, build_fn(skip))]
 #[allow(dead_code)]
@@ -141,42 +507,61 @@ pub struct HnswRecommenderArguments<P, D>
   where P: VectorProvider<usize>,
         D: HnswDistance<f32> {
   max_connections: usize,
+  /// the maximum number of graph layers. Only graphs with
+  /// [`DUMP_N_LAYERS`] layers can be saved, see [`check_dump_layers`].
   n_layers: usize,
   ef_coef: usize,
+  /// the default search width on the bottom layer of the graph, see
+  /// [`HnswRecommender::with_ef_search`]. Zero or unset uses at least 64.
+  #[builder(default)]
+  ef_search: usize,
   metric: D,
+  /// how distances are turned into scores, see
+  /// [`HnswRecommender::with_score_normalizer`]
+  #[builder(default)]
+  score_normalizer: ScoreNormalizer,
+  /// see [`HnswRecommender::with_min_score`]
+  #[builder(default, setter(strip_option))]
+  min_score: Option<f32>,
   vector_provider: P
 }
 
 impl<P, D> HnswRecommenderBuilder<P, D>
   where P: VectorProvider<usize>,
-        D: HnswDistance<f32> + Send + Sync {
+        D: HnswDistance<f32> + ScoredDistance + Send + Sync {
   pub fn build(self) -> Result<HnswRecommender<'static, D>, HnswRecommenderBuilderError> {
     let span  = span!(Level::DEBUG, "hnsw-init");
     let _guard = span.enter();
     let provider = Self::unwrap_field(self.vector_provider, "vector_provider")?;
-    let (cache, order) = KeyedVectorCache::for_provider(provider).into();
+    let cache = KeyedVectorCache::for_provider(provider)?;
+    let store = read_lock(&cache.store);
     debug!("Initializing index");
     let mut index = Hnsw::new(
       Self::unwrap_field(self.max_connections, "max_connections")?,
-      cache.vectors.dim().0,
+      store.vectors.dim().0,
       Self::unwrap_field(self.n_layers, "n_layers")?,
       Self::unwrap_field(self.ef_coef, "ef_coefficient")?,
       Self::unwrap_field(self.metric, "distance_metric")?
     );
     index.set_extend_candidates(false);
-    cache.vectors.axis_iter(Axis(0))
+    store.vectors.axis_iter(Axis(0))
       .into_par_iter()
-      .zip(order.into_par_iter())
-      .inspect(|(_, key)| trace!("Inserting key \"{}\" into the index", key))
-      .try_for_each(|(row, key)| {
-        row.as_slice()
-          .map(|unwrapped| index.insert_slice((unwrapped, key)))
+      .enumerate()
+      .inspect(|(row, _)| trace!("Inserting key \"{}\" into the index", store.keys[*row]))
+      .try_for_each(|(row, vector)| {
+        vector.as_slice()
+          .map(|unwrapped| index.insert_slice((unwrapped, row)))
           .ok_or_else(|| HnswRecommenderBuilderError::ValidationError("coudn't init index".to_string()))
       })?;
     debug!("Index initialized");
+    drop(store);
     // insert into index
     index.set_searching_mode(true);
-    Ok(HnswRecommender::new(index, cache))
+    let ef_search = self.ef_search.filter(|ef_search| *ef_search > 0);
+    Ok(HnswRecommender::new(index, cache)
+      .with_ef_search(ef_search)
+      .with_score_normalizer(self.score_normalizer.unwrap_or_default())
+      .with_min_score(self.min_score.flatten()))
   }
 
   fn unwrap_field<T>(val: Option<T>, name: &'static str) -> Result<T, HnswRecommenderBuilderError> {
@@ -184,33 +569,390 @@ impl<P, D> HnswRecommenderBuilder<P, D>
   }
 }
 
+impl<'a, D> HnswRecommender<'a, D>
+  where D: HnswDistance<f32> + Send + Sync {
+  /// Dump the graph, the query settings and the vector cache into `dir`,
+  /// creating it if needed. Existing dumps in the directory are
+  /// overwritten.
+  pub fn save<PathRef>(&self, dir: PathRef) -> Result<(), PersistError>
+    where PathRef: AsRef<Path> {
+    let span = span!(Level::DEBUG, "hnsw-save");
+    let _guard = span.enter();
+    let dir = dir.as_ref();
+    check_dump_layers(read_lock(&self.index).get_max_level())?;
+    fs::create_dir_all(dir)?;
+    debug!("Dumping graph to {:?}", dir);
+    // hnsw_rs dumps relative to the working directory, so the basename
+    // carries the target directory.
+    let basename = dir.join(GRAPH_BASENAME).to_string_lossy().into_owned();
+    read_lock(&self.index).file_dump(&basename)
+      .map_err(PersistError::Graph)?;
+    debug!("Dumping vector cache");
+    let mut out = BufWriter::new(File::create(dir.join(VECTOR_CACHE_FILE))?);
+    out.write_all(DUMP_MAGIC)?;
+    out.write_all(&DUMP_FORMAT_VERSION.to_le_bytes())?;
+    self.write_settings(&mut out)?;
+    self.vector_cache.write_to(&mut out)?;
+    out.flush()?;
+    Ok(())
+  }
+}
+
+impl<D> HnswRecommender<'static, D>
+  where D: HnswDistance<f32> + Send + Sync {
+  /// Reopen a recommender written by [`HnswRecommender::save`] without
+  /// reinserting its vectors, with the query settings it was saved with.
+  /// `metric` must be the distance the index was built with.
+  pub fn load<PathRef>(dir: PathRef, metric: D) -> Result<Self, PersistError>
+    where PathRef: AsRef<Path> {
+    let span = span!(Level::DEBUG, "hnsw-load");
+    let _guard = span.enter();
+    let dir = dir.as_ref();
+    debug!("Loading vector cache from {:?}", dir);
+    let file = File::open(dir.join(VECTOR_CACHE_FILE))?;
+    let len = file.metadata()?.len();
+    let mut input = BufReader::new(file);
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != DUMP_MAGIC {
+      return Err(PersistError::InvalidHeader)
+    }
+    let version = read_u32(&mut input)?;
+    if version != DUMP_FORMAT_VERSION {
+      return Err(PersistError::IncompatibleVersion {
+        found: version,
+        expected: DUMP_FORMAT_VERSION
+      })
+    }
+    let (ef_search, score_normalizer, min_score) = Self::read_settings(&mut input)?;
+    let cache_len = len.checked_sub(DUMP_HEADER_LEN)
+      .ok_or(PersistError::InvalidHeader)?;
+    let vector_cache = KeyedVectorCache::read_from(&mut input, cache_len)?;
+    debug!("Loading graph");
+    let loader = GraphLoader::new(HnswIo::new(dir.to_path_buf(), GRAPH_BASENAME.to_string()));
+    // SAFETY: the graph is stored next to the loader in the recommender,
+    // which drops the graph first, and is dropped here before the loader
+    // on error.
+    let mut index = unsafe { loader.get() }.load_hnsw_with_dist(metric)
+      .map_err(PersistError::Graph)?;
+    let n_rows = read_lock(&vector_cache.store).keys.len();
+    if index.get_nb_point() != n_rows {
+      return Err(PersistError::Corrupt(format!(
+        "graph has {} points but the vector cache has {}",
+        index.get_nb_point(), n_rows
+      )))
+    }
+    index.set_extend_candidates(false);
+    index.set_searching_mode(true);
+    debug!("Loaded index with {} points", index.get_nb_point());
+    let mut recommender = HnswRecommender::new(index, vector_cache)
+      .with_ef_search(ef_search)
+      .with_score_normalizer(score_normalizer)
+      .with_min_score(min_score);
+    recommender.loader = Some(loader);
+    Ok(recommender)
+  }
+}
+
+impl<'a, D> HnswRecommender<'a, D>
+  where D: HnswDistance<f32> + Send + Sync {
+  /// Write the query settings as the default search width (0 if unset),
+  /// the minimum score (a presence flag and the score), the distance
+  /// transform (a tag and the exponential scale) and the dot transform's
+  /// tag, all little-endian.
+  fn write_settings<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+    out.write_all(&(self.ef_search.unwrap_or(0) as u64).to_le_bytes())?;
+    out.write_all(&[self.min_score.is_some() as u8])?;
+    out.write_all(&self.min_score.unwrap_or(0f32).to_le_bytes())?;
+    let (distance, scale) = match self.score_normalizer.distance {
+      DistanceTransform::Reciprocal => (0u8, 0f32),
+      DistanceTransform::Exponential { scale } => (1, scale.get()),
+      DistanceTransform::Negate => (2, 0f32)
+    };
+    out.write_all(&[distance])?;
+    out.write_all(&scale.to_le_bytes())?;
+    let dot = match self.score_normalizer.dot {
+      DotTransform::Raw => 0u8,
+      DotTransform::Sigmoid => 1
+    };
+    out.write_all(&[dot])
+  }
+
+  fn read_settings<R: Read>(input: &mut R)
+      -> Result<(Option<usize>, ScoreNormalizer, Option<f32>), PersistError> {
+    let ef_search = Some(read_u64(input)? as usize).filter(|ef_search| *ef_search > 0);
+    let has_min_score = read_u8(input)? != 0;
+    let min_score = Some(read_f32(input)?).filter(|_| has_min_score);
+    let distance = match (read_u8(input)?, read_f32(input)?) {
+      (0, _) => DistanceTransform::Reciprocal,
+      (1, scale) => DistanceTransform::exponential(scale)
+        .map_err(|e| PersistError::Corrupt(e.to_string()))?,
+      (2, _) => DistanceTransform::Negate,
+      (tag, _) => return Err(PersistError::Corrupt(format!("unknown distance transform {}", tag)))
+    };
+    let dot = match read_u8(input)? {
+      0 => DotTransform::Raw,
+      1 => DotTransform::Sigmoid,
+      tag => return Err(PersistError::Corrupt(format!("unknown dot transform {}", tag)))
+    };
+    Ok((ef_search, ScoreNormalizer::new(distance, dot), min_score))
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum PersistError {
+  #[error("couldn't access dump files")]
+  Io(#[from] std::io::Error),
+  #[error("couldn't dump or reload HNSW graph: {0}")]
+  Graph(anyhow::Error),
+  #[error("not a recommender dump")]
+  InvalidHeader,
+  #[error("dump format version {found} is incompatible with version {expected}")]
+  IncompatibleVersion {
+    found: u32,
+    expected: u32
+  },
+  #[error("dump is inconsistent: {0}")]
+  Corrupt(String),
+  #[error("only graphs with {DUMP_N_LAYERS} layers can be dumped, not {0}")]
+  UnsupportedLayers(usize)
+}
+
+/// Check that a graph built with `n_layers` can be saved, before spending
+/// a build on it.
+pub fn check_dump_layers(n_layers: usize) -> Result<(), PersistError> {
+  match n_layers.min(DUMP_N_LAYERS) == DUMP_N_LAYERS {
+    true => Ok(()),
+    false => Err(PersistError::UnsupportedLayers(n_layers))
+  }
+}
+
+impl KeyedVectorCache<usize> {
+  /// Write the cache as its row count and dimensions followed by each
+  /// row's key, liveness flag and vector, all little-endian.
+  fn write_to<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+    let store = read_lock(&self.store);
+    let (n_rows, dims) = store.vectors.dim();
+    out.write_all(&(n_rows as u64).to_le_bytes())?;
+    out.write_all(&(dims as u64).to_le_bytes())?;
+    let rows = store.keys.iter()
+      .zip(store.live.iter())
+      .zip(store.vectors.axis_iter(Axis(0)));
+    for ((key, live), vector) in rows {
+      out.write_all(&(*key as u64).to_le_bytes())?;
+      out.write_all(&[*live as u8])?;
+      for value in vector.iter() {
+        out.write_all(&value.to_le_bytes())?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Read a cache written by [`KeyedVectorCache::write_to`] from the `len`
+  /// bytes left in `input`. The header is checked against `len` before
+  /// anything is allocated for the rows.
+  fn read_from<R: Read>(input: &mut R, len: u64) -> Result<Self, PersistError> {
+    let n_rows = read_u64(input)?;
+    let dims = read_u64(input)?;
+    // each row is a key, a liveness flag and the vector
+    let expected_len = dims.checked_mul(4)
+      .and_then(|vector_len| vector_len.checked_add(8 + 1))
+      .and_then(|row_len| row_len.checked_mul(n_rows))
+      .and_then(|rows_len| rows_len.checked_add(CACHE_HEADER_LEN));
+    if expected_len != Some(len) {
+      return Err(PersistError::Corrupt(format!(
+        "header describes {} vectors of {} dimensions, which doesn't match the file's length",
+        n_rows, dims
+      )))
+    }
+    let (n_rows, dims) = (n_rows as usize, dims as usize);
+    trace!("Reading {} vectors of {} dimensions", n_rows, dims);
+    let mut keys = Vec::with_capacity(n_rows);
+    let mut live = Vec::with_capacity(n_rows);
+    let mut values = Vec::with_capacity(n_rows * dims);
+    for _ in 0..n_rows {
+      keys.push(read_u64(input)? as usize);
+      live.push(read_u8(input)? != 0);
+      for _ in 0..dims {
+        values.push(read_f32(input)?);
+      }
+    }
+    let n_live = live.iter().filter(|live| **live).count();
+    let vectors = Array2::from_shape_vec((n_rows, dims), values)
+      .map_err(|e| PersistError::Corrupt(e.to_string()))?;
+    let cache = Self::new(vectors, keys, live);
+    if cache.id_to_vector.len() != n_live {
+      return Err(PersistError::Corrupt("duplicate live keys".to_string()))
+    }
+    Ok(cache)
+  }
+}
+
+fn read_u8<R: Read>(input: &mut R) -> std::io::Result<u8> {
+  let mut buf = [0u8; 1];
+  input.read_exact(&mut buf)?;
+  Ok(buf[0])
+}
+
+fn read_u32<R: Read>(input: &mut R) -> std::io::Result<u32> {
+  let mut buf = [0u8; 4];
+  input.read_exact(&mut buf)?;
+  Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> std::io::Result<u64> {
+  let mut buf = [0u8; 8];
+  input.read_exact(&mut buf)?;
+  Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f32<R: Read>(input: &mut R) -> std::io::Result<f32> {
+  let mut buf = [0u8; 4];
+  input.read_exact(&mut buf)?;
+  Ok(f32::from_le_bytes(buf))
+}
+
 #[cfg(feature = "space")]
 impl<'a, D> NavigableIndex for HnswRecommender<'a, D>
-  where D: HnswDistance<f32> + Send + Sync {
+  where D: HnswDistance<f32> + ScoredDistance + Send + Sync {
   type Key = usize;
   type Point = Vec<f32>;
-  type Neighbors = Map<IntoIter<Neighbour>, fn(Neighbour) -> Distance<usize>>;
+  type Neighbors = Vec<Distance<usize>>;
 
-  fn get_point(&self, key: &Self::Key) -> Option<Self::Point> {
+  fn get_point(&self, key: &Self::Key) -> Result<Option<Self::Point>, RecommendError> {
     trace!("Retrieving point");
-    self.vector_cache.get_vector(key)
+    Ok(self.vector_cache.get_vector(key))
   }
 
-  fn search(&self, subject: &Self::Point, n_items: u16) -> Self::Neighbors {
-    trace!("Searching for point in index");
-    self.index.search(subject.as_slice(), n_items as usize, 20)
-      .tap(|results| trace!("Searched returned {} results", results.len()))
-      .into_iter()
-      .map(Distance::from)
+  fn search(&self, subject: &Self::Point, n_items: u16) -> Result<Self::Neighbors, RecommendError> {
+    Ok(self.search_neighbors(subject, n_items, None, None))
+  }
+
+  fn score(&self, distance: f32) -> f32 {
+    self.score_normalizer.score::<D>(distance)
+  }
+
+  fn min_score(&self) -> Option<f32> {
+    self.min_score
   }
 }
 
-#[cfg(feature = "space")]
-impl From<Neighbour> for Distance<usize> {
-  fn from(value: Neighbour) -> Self {
-    Distance::new(
-      value.d_id,
-      value.distance
-    )
+impl ScoredDistance for dist::DistCosine {
+  const METRIC: Metric = Metric::Cosine;
+
+  /// hnsw_rs reports `1 - cos`.
+  fn canonical(distance: f32) -> f32 {
+    1f32 - distance
+  }
+}
+
+// hnsw_rs's `DistDot` asserts its vectors have unit norm, panicking on
+// ordinary input, so it has no `ScoredDistance` impl and can't build a
+// recommender. Normalized vectors rank the same under `DistCosine`.
+
+impl ScoredDistance for dist::DistL2 {
+  const METRIC: Metric = Metric::Euclidean;
+
+  fn canonical(distance: f32) -> f32 {
+    distance
+  }
+}
+
+impl ScoredDistance for dist::DistL1 {
+  const METRIC: Metric = Metric::Manhattan;
+
+  fn canonical(distance: f32) -> f32 {
+    distance
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, process, vec};
+
+  use super::*;
+  use crate::KeyedVector;
+
+  struct Vectors(vec::IntoIter<KeyedVector<usize>>);
+
+  impl Iterator for Vectors {
+    type Item = KeyedVector<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+      self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+      self.0.size_hint()
+    }
+  }
+
+  impl VectorProvider<usize> for Vectors {
+    fn vector_dimensions(&self) -> u16 {
+      2
+    }
+  }
+
+  fn vectors(keys: &[usize]) -> Vectors {
+    Vectors(keys.iter()
+      .map(|key| KeyedVector::new(*key, vec![*key as f32, 1.0]))
+      .collect::<Vec<_>>()
+      .into_iter())
+  }
+
+  fn build(provider: Vectors, n_layers: usize)
+      -> Result<HnswRecommender<'static, dist::DistL2>, HnswRecommenderBuilderError> {
+    HnswRecommender::builder()
+      .max_connections(8)
+      .n_layers(n_layers)
+      .ef_coef(32)
+      .ef_search(16)
+      .min_score(0.15)
+      .metric(dist::DistL2)
+      .vector_provider(provider)
+      .build()
+  }
+
+  fn ranked(recommender: &HnswRecommender<'static, dist::DistL2>) -> Vec<(usize, f32)> {
+    let recs: RecommendationList<usize> = recommender.recommend(&3usize, 10).unwrap();
+    recs.0.iter().map(|rec| (rec.item_id, rec.score)).collect()
+  }
+
+  fn temp_dir(name: &str) -> std::path::PathBuf {
+    env::temp_dir().join(format!("hnsw-recommender-{}-{}", name, process::id()))
+  }
+
+  #[test]
+  fn duplicate_keys_are_rejected() {
+    match build(vectors(&[1, 2, 1]), DUMP_N_LAYERS) {
+      Err(HnswRecommenderBuilderError::ValidationError(message)) =>
+        assert_eq!(message, "duplicate key 1"),
+      other => panic!("expected a validation error, got {:?}", other.err())
+    }
+  }
+
+  #[test]
+  fn save_and_load_round_trip() {
+    let dir = temp_dir("round-trip");
+    let recommender = build(vectors(&[0, 2, 3, 7, 10]), DUMP_N_LAYERS).unwrap();
+    recommender.save(&dir).unwrap();
+    let loaded = HnswRecommender::load(&dir, dist::DistL2);
+    fs::remove_dir_all(&dir).ok();
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.n_items(), 5);
+    assert_eq!(loaded.ef_search, Some(16));
+    assert_eq!(loaded.min_score, Some(0.15));
+    // 10 is 7 away from 3, scoring below the minimum
+    assert_eq!(ranked(&loaded), vec![(2, 0.5), (0, 0.25), (7, 0.2)]);
+    assert_eq!(ranked(&loaded), ranked(&recommender));
+  }
+
+  #[test]
+  fn save_rejects_fewer_layers() {
+    let dir = temp_dir("layers");
+    let recommender = build(vectors(&[1, 2, 3]), 4).unwrap();
+    assert!(matches!(recommender.save(&dir), Err(PersistError::UnsupportedLayers(4))));
+    assert!(!dir.exists());
+    assert!(check_dump_layers(DUMP_N_LAYERS + 1).is_ok());
   }
 }
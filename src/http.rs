@@ -0,0 +1,290 @@
+use std::{
+  hash::Hash,
+  sync::Arc
+};
+
+use axum::{
+  Json,
+  Router,
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  routing::{get, post}
+};
+use serde::{
+  Deserialize,
+  Serialize,
+  de::DeserializeOwned
+};
+use serde_json::{Value, json};
+use tokio::task;
+use tracing::{Level, span, debug};
+
+use super::{
+  RecommendRequest,
+  Recommender,
+  RecommendError,
+  RecommendationList
+};
+
+/// The number of recommendations returned when a request doesn't say.
+const DEFAULT_N_ITEMS: u16 = 10;
+
+struct ServerState<Rc: ?Sized> {
+  recommender: Arc<Rc>,
+  metadata: Value
+}
+
+/// Routes serving `recommender` as JSON:
+///
+/// - `GET /recommend/{id}?n=` recommends for a stored item
+/// - `POST /recommend` takes one of `{"id": ..}`, `{"ids": [..]}`,
+///   `{"vector": [..]}` or `{"vectors": [[..], ..]}`, plus an optional `n`.
+///   Batches answer with one entry per subject, either a list or an
+///   `{"error": ..}` object.
+/// - `GET /health`
+/// - `GET /metadata` returns `metadata`, e.g. the build parameters
+///
+/// Handlers hand each search to `spawn_blocking`, so a slow query doesn't
+/// stall the runtime's workers serving other connections.
+pub fn router<K, R, Rc>(recommender: Arc<Rc>, metadata: Value) -> Router
+  where K: DeserializeOwned + Send + 'static,
+        R: Serialize + Eq + Hash + Send + 'static,
+        Rc: Recommender<K, R> + Send + Sync + ?Sized + 'static {
+  let state = Arc::new(ServerState { recommender, metadata });
+  Router::new()
+    .route("/recommend/{id}", get(recommend_item::<K, R, Rc>))
+    .route("/recommend", post(recommend::<K, R, Rc>))
+    .route("/health", get(health))
+    .route("/metadata", get(describe::<Rc>))
+    .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct ItemParams {
+  n: Option<u16>
+}
+
+#[derive(Deserialize)]
+struct RecommendBody<K> {
+  #[serde(flatten)]
+  subjects: Subjects<K>,
+  n: Option<u16>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Subjects<K> {
+  Id(K),
+  Ids(Vec<K>),
+  Vector(Vec<f32>),
+  Vectors(Vec<Vec<f32>>)
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum RecommendResponse<R> {
+  Single(RecommendationList<R>),
+  Batch(Vec<BatchEntry<R>>)
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BatchEntry<R> {
+  Recommendations(RecommendationList<R>),
+  Error { error: String }
+}
+
+impl<R> From<Result<RecommendationList<R>, RecommendError>> for BatchEntry<R> {
+  fn from(result: Result<RecommendationList<R>, RecommendError>) -> Self {
+    match result {
+      Ok(recs) => BatchEntry::Recommendations(recs),
+      Err(e) => BatchEntry::Error { error: e.to_string() }
+    }
+  }
+}
+
+async fn recommend_item<K, R, Rc>(
+  State(state): State<Arc<ServerState<Rc>>>,
+  Path(id): Path<K>,
+  Query(params): Query<ItemParams>
+) -> Result<Json<RecommendationList<R>>, ApiError>
+  where R: Eq + Hash + Send + 'static,
+        K: Send + 'static,
+        Rc: Recommender<K, R> + Send + Sync + ?Sized + 'static {
+  let n_items = params.n.unwrap_or(DEFAULT_N_ITEMS);
+  let recs = blocking(move || {
+    state.recommender.recommend_with(&RecommendRequest::item(&id, n_items))
+  }).await??;
+  Ok(Json(recs))
+}
+
+async fn recommend<K, R, Rc>(
+  State(state): State<Arc<ServerState<Rc>>>,
+  Json(body): Json<RecommendBody<K>>
+) -> Result<Json<RecommendResponse<R>>, ApiError>
+  where R: Eq + Hash + Send + 'static,
+        K: Send + 'static,
+        Rc: Recommender<K, R> + Send + Sync + ?Sized + 'static {
+  let n_items = body.n.unwrap_or(DEFAULT_N_ITEMS);
+  let recommender = state.recommender.clone();
+  let response = blocking(move || {
+    let span = span!(Level::DEBUG, "http-recommend");
+    let _guard = span.enter();
+    let response = match body.subjects {
+      Subjects::Id(id) => RecommendResponse::Single(
+        recommender.recommend_with(&RecommendRequest::item(&id, n_items))?
+      ),
+      Subjects::Vector(vector) => RecommendResponse::Single(
+        recommender.recommend_with(&RecommendRequest::vector(&vector, n_items))?
+      ),
+      Subjects::Ids(ids) => {
+        debug!("Recommending for {} items", ids.len());
+        RecommendResponse::Batch(recommender.recommend_batch(&ids, n_items)
+          .into_iter()
+          .map(BatchEntry::from)
+          .collect())
+      },
+      Subjects::Vectors(vectors) => {
+        debug!("Recommending for {} vectors", vectors.len());
+        RecommendResponse::Batch(vectors.iter()
+          .map(|vector| recommender.recommend_with(&RecommendRequest::vector(vector, n_items)))
+          .map(BatchEntry::from)
+          .collect())
+      }
+    };
+    Ok::<_, RecommendError>(response)
+  }).await??;
+  Ok(Json(response))
+}
+
+async fn health() -> Json<Value> {
+  Json(json!({ "status": "ok" }))
+}
+
+async fn describe<Rc: ?Sized>(State(state): State<Arc<ServerState<Rc>>>) -> Json<Value> {
+  Json(state.metadata.clone())
+}
+
+/// Run a search on the blocking thread pool.
+async fn blocking<T, F>(search: F) -> Result<T, ApiError>
+  where F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static {
+  task::spawn_blocking(search).await
+    .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// A failed request, answered as `{"error": ..}`.
+struct ApiError(StatusCode, String);
+
+impl From<RecommendError> for ApiError {
+  fn from(e: RecommendError) -> Self {
+    let status = match e {
+      RecommendError::NotFound => StatusCode::NOT_FOUND,
+      RecommendError::IncompatibleId
+        | RecommendError::UnsupportedRequest
+        | RecommendError::DimensionMismatch { .. } => StatusCode::BAD_REQUEST,
+      #[allow(unreachable_patterns)]
+      _ => StatusCode::INTERNAL_SERVER_ERROR
+    };
+    ApiError(status, e.to_string())
+  }
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    (self.0, Json(json!({ "error": self.1 }))).into_response()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::{
+    body::{self, Body},
+    http::{Method, Request, header}
+  };
+  use tower::ServiceExt;
+
+  use super::*;
+  use crate::{Recommendation, Subject};
+
+  /// Knows item 1, and recommends a vector's length for vectors.
+  struct Fixed;
+
+  impl Recommender<u64, u64> for Fixed {
+    fn recommend(&self, item_id: &u64, n_items: u16)
+        -> Result<RecommendationList<u64>, RecommendError> {
+      match item_id {
+        1 => Ok(RecommendationList(vec![Recommendation::new(2, 0.75), Recommendation::new(3, 0.5)]
+          .into_iter()
+          .take(n_items as usize)
+          .collect())),
+        _ => Err(RecommendError::NotFound)
+      }
+    }
+
+    fn recommend_with(&self, request: &RecommendRequest<u64, u64>)
+        -> Result<RecommendationList<u64>, RecommendError> {
+      match request.subject {
+        Subject::Item(item_id) => self.recommend(item_id, request.n_items),
+        Subject::Vector(vector) => Ok(RecommendationList(vec![Recommendation::new(vector.len() as u64, 1.0)]))
+      }
+    }
+  }
+
+  async fn call(request: Request<Body>) -> (StatusCode, Value) {
+    let router = router::<u64, u64, _>(Arc::new(Fixed), json!({ "backend": "fixed" }));
+    let response = router.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+  }
+
+  async fn get(uri: &str) -> (StatusCode, Value) {
+    call(Request::get(uri).body(Body::empty()).unwrap()).await
+  }
+
+  async fn post(body: Value) -> (StatusCode, Value) {
+    let request = Request::builder()
+      .method(Method::POST)
+      .uri("/recommend")
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from(body.to_string()))
+      .unwrap();
+    call(request).await
+  }
+
+  #[tokio::test]
+  async fn recommends_for_items_by_path() {
+    let (status, body) = get("/recommend/1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!([{ "item_id": 2, "score": 0.75 }, { "item_id": 3, "score": 0.5 }]));
+    assert_eq!(get("/recommend/1?n=1").await.1, json!([{ "item_id": 2, "score": 0.75 }]));
+  }
+
+  #[tokio::test]
+  async fn unknown_items_are_not_found() {
+    let (status, body) = get("/recommend/9").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body["error"].is_string());
+    assert_eq!(post(json!({ "id": 9 })).await.0, StatusCode::NOT_FOUND);
+  }
+
+  #[tokio::test]
+  async fn recommends_for_each_body_form() {
+    assert_eq!(post(json!({ "id": 1, "n": 1 })).await, (StatusCode::OK, json!([{ "item_id": 2, "score": 0.75 }])));
+    assert_eq!(post(json!({ "vector": [0.5, 1.0] })).await.1, json!([{ "item_id": 2, "score": 1.0 }]));
+    let (status, body) = post(json!({ "ids": [1, 9], "n": 1 })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0], json!([{ "item_id": 2, "score": 0.75 }]));
+    assert!(body[1]["error"].is_string());
+    let (_, body) = post(json!({ "vectors": [[1.0], [1.0, 2.0, 3.0]] })).await;
+    assert_eq!(body, json!([[{ "item_id": 1, "score": 1.0 }], [{ "item_id": 3, "score": 1.0 }]]));
+  }
+
+  #[tokio::test]
+  async fn health_and_metadata() {
+    assert_eq!(get("/health").await, (StatusCode::OK, json!({ "status": "ok" })));
+    assert_eq!(get("/metadata").await, (StatusCode::OK, json!({ "backend": "fixed" })));
+  }
+}
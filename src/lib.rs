@@ -1,36 +1,218 @@
 #[cfg(feature = "annoy")]
 pub mod annoy_recommender;
+#[cfg(feature = "brute_force")]
+pub mod brute_force;
+#[cfg(feature = "config")]
+pub mod config;
 pub mod error;
+pub mod eval;
+pub mod filter;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 #[cfg(feature = "hnsw")]
 pub mod hnsw_recommender;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod list;
 pub mod mapping;
+#[cfg(feature = "providers")]
+pub mod providers;
 #[cfg(feature = "random_recommender")]
 pub mod random;
+pub mod registry;
+pub mod request;
+#[cfg(feature = "space")]
+pub mod score;
 #[cfg(feature = "space")]
 pub mod spatial;
+pub mod transform;
 pub mod types;
 
-#[cfg(any(feature = "annoy", feature = "hnsw"))]
+#[cfg(any(
+  feature = "annoy",
+  feature = "brute_force",
+  feature = "hnsw",
+  feature = "random_recommender"
+))]
 #[macro_use]
 extern crate derive_builder;
 
 #[cfg(feature = "random_recommender")]
-pub use random::RandomRecommender;
+pub use random::{RandomRecommender, SeededRandomRecommender};
 #[cfg(feature = "annoy")]
 pub use annoy_recommender::AnnoyRecommender;
+#[cfg(feature = "brute_force")]
+pub use brute_force::BruteForceRecommender;
 #[cfg(feature = "hnsw")]
 pub use hnsw_recommender::HnswRecommender;
 pub use list::RecommendationList;
+pub use request::{RecommendRequest, SearchParams, Subject};
 pub use error::RecommendError;
+pub use filter::CandidateFilter;
+pub use registry::RecommenderRegistry;
 pub use types::Recommendation;
 
+use std::{
+  hash::Hash,
+  sync::Arc
+};
+
 pub trait Recommender<K, R> {
+  /// Recommend `n_items` for `item_id`. This is the plain form of
+  /// [`Recommender::recommend_with`], without any options.
+  fn recommend(&self, item_id: &K, n_items: u16)
+      -> Result<RecommendationList<R>, RecommendError>;
+
+  /// Serve a request with all of its options. By default item subjects are
+  /// served by over-fetching with [`Recommender::recommend`] and vector
+  /// subjects aren't supported.
+  fn recommend_with(&self, request: &RecommendRequest<K, R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    let Subject::Item(item_id) = request.subject else {
+      return Err(RecommendError::UnsupportedRequest)
+    };
+    request.overfetch(|n| self.recommend(item_id, n))
+  }
+
+  /// Recommend for several subjects at once. The results are returned in the
+  /// same order as `item_ids`. Backends that can share work between lookups
+  /// (read transactions, thread pools) should override this.
+  fn recommend_batch(&self, item_ids: &[K], n_items: u16)
+      -> Vec<Result<RecommendationList<R>, RecommendError>> {
+    item_ids.iter()
+      .map(|item_id| self.recommend(item_id, n_items))
+      .collect()
+  }
+
+  /// Recommend up to `n_items` that pass `filter`.
+  fn recommend_filtered(&self, item_id: &K, n_items: u16, filter: &CandidateFilter<R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    self.recommend_with(&RecommendRequest::item(item_id, n_items).with_filter(filter))
+  }
+}
+
+/// A recommender whose backend is picked at runtime, e.g. from a config or
+/// a [`RecommenderRegistry`].
+pub type DynRecommender<K, R> = dyn Recommender<K, R> + Send + Sync;
+
+/// Compiles only if `T` can be shared between threads.
+const fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+
+// `Recommender` has to stay object safe for `DynRecommender` to exist.
+const _: () = assert_send_sync::<DynRecommender<u64, u64>>();
+
+// Backends are shared between request handlers.
+const _: () = {
+  #[cfg(feature = "annoy")]
+  assert_send_sync::<AnnoyRecommender<annoy_recommender::distances::Angular>>();
+  #[cfg(feature = "brute_force")]
+  assert_send_sync::<BruteForceRecommender>();
+  #[cfg(feature = "hnsw")]
+  assert_send_sync::<HnswRecommender<'static, hnsw_recommender::dist::DistCosine>>();
+};
+
+impl<K, R, T> Recommender<K, R> for Box<T>
+  where T: Recommender<K, R> + ?Sized {
+  fn recommend(&self, item_id: &K, n_items: u16)
+      -> Result<RecommendationList<R>, RecommendError> {
+    (**self).recommend(item_id, n_items)
+  }
+
+  fn recommend_with(&self, request: &RecommendRequest<K, R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    (**self).recommend_with(request)
+  }
+
+  fn recommend_batch(&self, item_ids: &[K], n_items: u16)
+      -> Vec<Result<RecommendationList<R>, RecommendError>> {
+    (**self).recommend_batch(item_ids, n_items)
+  }
+
+  fn recommend_filtered(&self, item_id: &K, n_items: u16, filter: &CandidateFilter<R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    (**self).recommend_filtered(item_id, n_items, filter)
+  }
+}
+
+impl<K, R, T> Recommender<K, R> for Arc<T>
+  where T: Recommender<K, R> + ?Sized {
   fn recommend(&self, item_id: &K, n_items: u16)
+      -> Result<RecommendationList<R>, RecommendError> {
+    (**self).recommend(item_id, n_items)
+  }
+
+  fn recommend_with(&self, request: &RecommendRequest<K, R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    (**self).recommend_with(request)
+  }
+
+  fn recommend_batch(&self, item_ids: &[K], n_items: u16)
+      -> Vec<Result<RecommendationList<R>, RecommendError>> {
+    (**self).recommend_batch(item_ids, n_items)
+  }
+
+  fn recommend_filtered(&self, item_id: &K, n_items: u16, filter: &CandidateFilter<R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    (**self).recommend_filtered(item_id, n_items, filter)
+  }
+}
+
+/// A recommender that can search from an arbitrary query vector, such as a
+/// user embedding, rather than from a stored item.
+pub trait VectorRecommender<R> {
+  fn recommend_by_vector(&self, vector: &[f32], n_items: u16)
       -> Result<RecommendationList<R>, RecommendError>;
+
+  /// Like [`VectorRecommender::recommend_by_vector`], but only returning
+  /// items that pass `filter`.
+  fn recommend_by_vector_filtered(&self, vector: &[f32], n_items: u16, filter: &CandidateFilter<R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    filter::overfetch(
+      n_items, None,
+      |rec| filter.accepts(&rec.item_id),
+      |n| self.recommend_by_vector(vector, n)
+    ).map(RecommendationList)
+  }
+}
+
+impl<R, T> VectorRecommender<R> for Box<T>
+  where T: VectorRecommender<R> + ?Sized {
+  fn recommend_by_vector(&self, vector: &[f32], n_items: u16)
+      -> Result<RecommendationList<R>, RecommendError> {
+    (**self).recommend_by_vector(vector, n_items)
+  }
+
+  fn recommend_by_vector_filtered(&self, vector: &[f32], n_items: u16, filter: &CandidateFilter<R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    (**self).recommend_by_vector_filtered(vector, n_items, filter)
+  }
 }
 
-pub trait VectorProvider<K>: ExactSizeIterator<Item = KeyedVector<K>> {
+impl<R, T> VectorRecommender<R> for Arc<T>
+  where T: VectorRecommender<R> + ?Sized {
+  fn recommend_by_vector(&self, vector: &[f32], n_items: u16)
+      -> Result<RecommendationList<R>, RecommendError> {
+    (**self).recommend_by_vector(vector, n_items)
+  }
+
+  fn recommend_by_vector_filtered(&self, vector: &[f32], n_items: u16, filter: &CandidateFilter<R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    (**self).recommend_by_vector_filtered(vector, n_items, filter)
+  }
+}
+
+/// Keyed vectors to build an index from. Only a size hint is needed, so
+/// providers can stream vectors without counting them first.
+pub trait VectorProvider<K>: Iterator<Item = KeyedVector<K>> {
   fn vector_dimensions(&self) -> u16;
 }
 
@@ -51,19 +233,3 @@ impl<K> From<KeyedVector<K>> for (K, Vec<f32>) {
   }
 }
 
-
-// impl<K, I> Recommender<K> for I
-//   where I: NavigableIndex<Key = K>,
-//         K: Clone + PartialEq {
-//   fn recommend(&self, item_id: &K, n_items: u16)
-//       -> Result<impl IntoIterator<Item = Recommendation<K>>, RecommendError<K>> {
-//     self.get_point(item_id)
-//       .ok_or_else(|| RecommendError::NotFound(item_id.clone()))
-//       .map(|point| {
-//         self.search(&point, n_items)
-//           .into_iter()
-//           .filter(|dist| dist.item_id != (*item_id))
-//           .map(Recommendation::from)
-//       })
-//   }
-// }
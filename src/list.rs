@@ -8,13 +8,11 @@ pub struct RecommendationList<K>(pub Vec<Recommendation<K>>);
 impl<K> RecommendationList<K> {
 
   pub fn new_with_sort(mut recs: Vec<Recommendation<K>>) -> Self {
-    recs.sort_by(|this, other| {
-        other.score.partial_cmp(&this.score).unwrap()
-      }
-    );
+    recs.sort_by(|this, other| other.score.total_cmp(&this.score));
     Self(recs)
   }
 
+  #[allow(clippy::should_implement_trait)]
   pub fn from_iter<I>(value: I) -> Self
     where I: IntoIterator,
           I::Item: Into<Recommendation<K>> {
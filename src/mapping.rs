@@ -1,7 +1,18 @@
+use std::{
+  collections::HashSet,
+  hash::Hash,
+  marker::PhantomData
+};
+
 use super::{
+  CandidateFilter,
+  Recommendation,
+  RecommendRequest,
   Recommender,
   RecommendError,
-  RecommendationList
+  RecommendationList,
+  Subject,
+  VectorRecommender
 };
 
 pub struct IdMappingRecommender<M, R> {
@@ -24,4 +35,103 @@ impl<M, R, InputKey, MappedKey, Rec> Recommender<InputKey, Rec> for IdMappingRec
       .ok_or(RecommendError::NotFound)
       .and_then(|key| self.recommender.recommend(key, n_items))
   }
+
+  fn recommend_with(&self, request: &RecommendRequest<InputKey, Rec>)
+        -> Result<RecommendationList<Rec>, RecommendError>
+      where Rec: Eq + Hash {
+    let subject = match request.subject {
+      Subject::Item(item_id) => Subject::Item(
+        (self.mapper)(item_id).ok_or(RecommendError::NotFound)?
+      ),
+      Subject::Vector(vector) => Subject::Vector(vector)
+    };
+    self.recommender.recommend_with(&RecommendRequest {
+      subject,
+      n_items: request.n_items,
+      offset: request.offset,
+      filter: request.filter,
+      exclude: request.exclude,
+      min_score: request.min_score,
+      search: request.search
+    })
+  }
+}
+
+/// Exposes a recommender that returns `N` keys, such as the `usize` keys of
+/// the hnsw and brute force backends, with keys of type `R`, e.g. `u64`.
+/// Recommendations whose keys don't fit in `R` are dropped.
+pub struct RekeyedRecommender<Rc, N> {
+  recommender: Rc,
+  native: PhantomData<fn() -> N>
+}
+
+impl<Rc, N> RekeyedRecommender<Rc, N> {
+  pub fn new(recommender: Rc) -> Self {
+    RekeyedRecommender { recommender, native: PhantomData }
+  }
+}
+
+fn rekey<N, R>(list: RecommendationList<N>) -> RecommendationList<R>
+  where R: TryFrom<N> {
+  RecommendationList(list.0.into_iter()
+    .filter_map(|rec| R::try_from(rec.item_id).ok()
+      .map(|item_id| Recommendation::new(item_id, rec.score)))
+    .collect())
+}
+
+impl<Rc, K, N, R> Recommender<K, R> for RekeyedRecommender<Rc, N>
+  where Rc: Recommender<K, N>,
+        N: Clone + Eq + Hash,
+        R: TryFrom<N> + TryInto<N> + Clone + Send + Sync + 'static {
+  fn recommend(&self, item_id: &K, n_items: u16)
+      -> Result<RecommendationList<R>, RecommendError> {
+    self.recommender.recommend(item_id, n_items).map(rekey)
+  }
+
+  fn recommend_with(&self, request: &RecommendRequest<K, R>)
+      -> Result<RecommendationList<R>, RecommendError>
+    where R: Eq + Hash {
+    // items that don't fit in `N` can't be in the index, so they're left
+    // out of the converted sets
+    let narrow = |items: &HashSet<R>| -> HashSet<N> {
+      items.iter().filter_map(|item| item.clone().try_into().ok()).collect()
+    };
+    let filter = request.filter.map(|filter| match filter {
+      CandidateFilter::Allow(items) => CandidateFilter::Allow(narrow(items)),
+      CandidateFilter::Deny(items) => CandidateFilter::Deny(narrow(items)),
+      filter => {
+        let filter = filter.clone();
+        CandidateFilter::predicate(move |item: &N| {
+          R::try_from(item.clone()).is_ok_and(|item| filter.accepts(&item))
+        })
+      }
+    });
+    let exclude = request.exclude.map(narrow);
+    self.recommender.recommend_with(&RecommendRequest {
+      subject: request.subject,
+      n_items: request.n_items,
+      offset: request.offset,
+      filter: filter.as_ref(),
+      exclude: exclude.as_ref(),
+      min_score: request.min_score,
+      search: request.search
+    }).map(rekey)
+  }
+
+  fn recommend_batch(&self, item_ids: &[K], n_items: u16)
+      -> Vec<Result<RecommendationList<R>, RecommendError>> {
+    self.recommender.recommend_batch(item_ids, n_items)
+      .into_iter()
+      .map(|result| result.map(rekey))
+      .collect()
+  }
+}
+
+impl<Rc, N, R> VectorRecommender<R> for RekeyedRecommender<Rc, N>
+  where Rc: VectorRecommender<N>,
+        R: TryFrom<N> {
+  fn recommend_by_vector(&self, vector: &[f32], n_items: u16)
+      -> Result<RecommendationList<R>, RecommendError> {
+    self.recommender.recommend_by_vector(vector, n_items).map(rekey)
+  }
 }
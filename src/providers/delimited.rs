@@ -0,0 +1,149 @@
+use std::{
+  io::Read,
+  marker::PhantomData,
+  str::FromStr
+};
+
+use csv::StringRecordsIntoIter;
+
+use super::{
+  LoadedVectors,
+  ProviderError,
+  RecordSource,
+  VectorStream,
+  collect
+};
+
+/// The layout of a CSV or TSV file holding one keyed vector per row: a key
+/// column, with every other column being a vector component.
+#[derive(Debug, Clone, Copy)]
+pub struct DelimitedFormat {
+  pub delimiter: u8,
+  /// the zero-based index of the key column
+  pub key_column: usize,
+  /// whether the first row holds column names rather than a vector
+  pub has_header: bool
+}
+
+impl DelimitedFormat {
+  pub fn csv() -> Self {
+    DelimitedFormat { delimiter: b',', key_column: 0, has_header: false }
+  }
+
+  pub fn tsv() -> Self {
+    DelimitedFormat { delimiter: b'\t', ..Self::csv() }
+  }
+
+  pub fn with_key_column(mut self, key_column: usize) -> Self {
+    self.key_column = key_column;
+    self
+  }
+
+  pub fn with_header(mut self, has_header: bool) -> Self {
+    self.has_header = has_header;
+    self
+  }
+}
+
+/// Read delimited keyed vectors. All rows must have as many components as
+/// the first.
+pub fn read<K, Rd>(input: Rd, format: DelimitedFormat) -> Result<LoadedVectors<K>, ProviderError>
+  where K: FromStr,
+        Rd: Read {
+  collect(DelimitedRecords::new(input, format))
+}
+
+/// Stream delimited keyed vectors, parsing rows as they are consumed.
+pub fn stream<K, Rd>(input: Rd, format: DelimitedFormat) -> Result<VectorStream<K>, ProviderError>
+  where K: FromStr + 'static,
+        Rd: Read + 'static {
+  VectorStream::new(DelimitedRecords::new(input, format))
+}
+
+struct DelimitedRecords<K, Rd> {
+  records: StringRecordsIntoIter<Rd>,
+  format: DelimitedFormat,
+  key: PhantomData<fn() -> K>
+}
+
+impl<K, Rd: Read> DelimitedRecords<K, Rd> {
+  fn new(input: Rd, format: DelimitedFormat) -> Self {
+    let reader = csv::ReaderBuilder::new()
+      .delimiter(format.delimiter)
+      .has_headers(format.has_header)
+      .flexible(true)
+      .from_reader(input);
+    DelimitedRecords { records: reader.into_records(), format, key: PhantomData }
+  }
+}
+
+impl<K: FromStr, Rd: Read> RecordSource<K> for DelimitedRecords<K, Rd> {
+  fn next_record(&mut self) -> Result<Option<(usize, K, Vec<f32>)>, ProviderError> {
+    let Some(record) = self.records.next() else {
+      return Ok(None)
+    };
+    let record = record.map_err(csv_error)?;
+    let line = record.position().map_or(0, |position| position.line() as usize);
+    let key_column = self.format.key_column;
+    let key = record.get(key_column)
+      .ok_or_else(|| ProviderError::malformed(line, "missing key column"))?
+      .trim()
+      .parse()
+      .map_err(|_| ProviderError::malformed(line, "invalid key"))?;
+    let vector = record.iter()
+      .enumerate()
+      .filter(|(column, _)| *column != key_column)
+      .map(|(column, value)| {
+        value.trim().parse::<f32>().map_err(|_| {
+          ProviderError::malformed(line, format!("invalid value in column {}", column + 1))
+        })
+      })
+      .collect::<Result<Vec<f32>, ProviderError>>()?;
+    Ok(Some((line, key, vector)))
+  }
+}
+
+fn csv_error(e: csv::Error) -> ProviderError {
+  match e.position() {
+    Some(position) => ProviderError::malformed(position.line() as usize, &e),
+    None => ProviderError::Io(e.into())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    VectorProvider,
+    providers::tests::{keyed, malformed, mismatch}
+  };
+
+  #[test]
+  fn csv_and_tsv_rows() {
+    let loaded = read::<u32, _>("1, 0.5,2\n2,3,-4\n".as_bytes(), DelimitedFormat::csv()).unwrap();
+    assert_eq!(loaded.vector_dimensions(), 2);
+    assert_eq!(keyed(loaded), vec![(1, vec![0.5, 2.0]), (2, vec![3.0, -4.0])]);
+    let format = DelimitedFormat::tsv().with_header(true).with_key_column(2);
+    let loaded = read::<String, _>("x\ty\tid\n1\t2\ta\n".as_bytes(), format).unwrap();
+    assert_eq!(keyed(loaded), vec![("a".to_string(), vec![1.0, 2.0])]);
+  }
+
+  #[test]
+  fn errors_name_the_line() {
+    let format = DelimitedFormat::csv().with_header(true);
+    let input = "id,x,y\n1,1,2\n2,1,z\n";
+    assert_eq!(malformed(read::<u32, _>(input.as_bytes(), format)), (3, "invalid value in column 3".to_string()));
+    let input = "id,x,y\n1,1,2\nb,1,2\n";
+    assert_eq!(malformed(read::<u32, _>(input.as_bytes(), format)), (3, "invalid key".to_string()));
+    let format = DelimitedFormat::csv().with_key_column(2);
+    assert_eq!(malformed(read::<u32, _>("1,2\n".as_bytes(), format)), (1, "missing key column".to_string()));
+    let (line, _) = malformed(read::<u32, _>(&b"1,2\n2,\xff\n"[..], DelimitedFormat::csv()));
+    assert_eq!(line, 2);
+  }
+
+  #[test]
+  fn rows_must_have_as_many_components_as_the_first() {
+    let input = "1,1,2\n2,1\n";
+    assert_eq!(mismatch(read::<u32, _>(input.as_bytes(), DelimitedFormat::csv())), (2, 2, 1));
+  }
+}
@@ -0,0 +1,100 @@
+use std::{
+  io::{BufRead, Lines},
+  iter::Enumerate,
+  marker::PhantomData
+};
+
+use serde::{
+  Deserialize,
+  de::DeserializeOwned
+};
+
+use super::{
+  LoadedVectors,
+  ProviderError,
+  RecordSource,
+  VectorStream,
+  collect
+};
+
+#[derive(Deserialize)]
+struct Record<K> {
+  id: K,
+  vector: Vec<f32>
+}
+
+/// Read JSON Lines holding one `{"id": .., "vector": [..]}` object per
+/// line. Blank lines are skipped. All vectors must have as many components
+/// as the first.
+pub fn read<K, Rd>(input: Rd) -> Result<LoadedVectors<K>, ProviderError>
+  where K: DeserializeOwned,
+        Rd: BufRead {
+  collect(JsonLines::new(input))
+}
+
+/// Stream JSON Lines, parsing objects as they are consumed.
+pub fn stream<K, Rd>(input: Rd) -> Result<VectorStream<K>, ProviderError>
+  where K: DeserializeOwned + 'static,
+        Rd: BufRead + 'static {
+  VectorStream::new(JsonLines::new(input))
+}
+
+struct JsonLines<K, Rd> {
+  lines: Enumerate<Lines<Rd>>,
+  key: PhantomData<fn() -> K>
+}
+
+impl<K, Rd: BufRead> JsonLines<K, Rd> {
+  fn new(input: Rd) -> Self {
+    JsonLines { lines: input.lines().enumerate(), key: PhantomData }
+  }
+}
+
+impl<K: DeserializeOwned, Rd: BufRead> RecordSource<K> for JsonLines<K, Rd> {
+  fn next_record(&mut self) -> Result<Option<(usize, K, Vec<f32>)>, ProviderError> {
+    for (i, line) in self.lines.by_ref() {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue
+      }
+      let record: Record<K> = serde_json::from_str(&line)
+        .map_err(|e| ProviderError::malformed(i + 1, e))?;
+      return Ok(Some((i + 1, record.id, record.vector)))
+    }
+    Ok(None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    VectorProvider,
+    providers::tests::{keyed, malformed, mismatch}
+  };
+
+  #[test]
+  fn blank_lines_are_skipped() {
+    let input = "{\"id\": \"a\", \"vector\": [1, 2.5]}\n\n  \n{\"id\": \"b\", \"vector\": [3, 4]}\n";
+    let loaded = read::<String, _>(input.as_bytes()).unwrap();
+    assert_eq!(loaded.vector_dimensions(), 2);
+    assert_eq!(keyed(loaded), vec![("a".to_string(), vec![1.0, 2.5]), ("b".to_string(), vec![3.0, 4.0])]);
+  }
+
+  #[test]
+  fn errors_name_the_line() {
+    let input = "{\"id\": 1, \"vector\": [1]}\n\n{\"id\": 2, \"vector\": [1}\n";
+    let (line, _) = malformed(read::<u32, _>(input.as_bytes()));
+    assert_eq!(line, 3);
+    let input = "{\"id\": 1, \"vector\": [1]}\n{\"vector\": [1]}\n";
+    let (line, message) = malformed(read::<u32, _>(input.as_bytes()));
+    assert_eq!(line, 2);
+    assert!(message.contains("missing field `id`"));
+  }
+
+  #[test]
+  fn vectors_must_have_as_many_components_as_the_first() {
+    let input = "{\"id\": 1, \"vector\": [1, 2]}\n\n{\"id\": 2, \"vector\": [1, 2, 3]}\n";
+    assert_eq!(mismatch(read::<u32, _>(input.as_bytes())), (3, 2, 3));
+  }
+}
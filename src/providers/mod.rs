@@ -0,0 +1,205 @@
+pub mod delimited;
+pub mod jsonl;
+pub mod npy;
+pub mod vecs;
+mod stream;
+
+use std::vec::IntoIter;
+
+use thiserror::Error;
+
+use super::{
+  KeyedVector,
+  VectorProvider
+};
+
+pub use delimited::DelimitedFormat;
+pub use stream::{
+  StreamStatus,
+  VectorStream
+};
+
+#[derive(Debug, Error)]
+pub enum ProviderError {
+  #[error("could not read vectors")]
+  Io(#[from] std::io::Error),
+  #[error("invalid header: {0}")]
+  InvalidHeader(String),
+  /// `record` is the line number for text formats, and the row number for
+  /// binary ones, both starting at 1.
+  #[error("record {record}: {message}")]
+  Malformed {
+    record: usize,
+    message: String
+  },
+  #[error("record {record} has {received} dimensions, expected {expected}")]
+  DimensionMismatch {
+    record: usize,
+    expected: usize,
+    received: usize
+  }
+}
+
+impl ProviderError {
+  fn malformed(record: usize, message: impl ToString) -> Self {
+    ProviderError::Malformed { record, message: message.to_string() }
+  }
+}
+
+/// Vectors read and validated up front, ready to build an index from.
+pub struct LoadedVectors<K> {
+  vectors: IntoIter<KeyedVector<K>>,
+  dimensions: u16
+}
+
+impl<K> LoadedVectors<K> {
+  pub fn new(vectors: Vec<KeyedVector<K>>, dimensions: u16) -> Self {
+    LoadedVectors { vectors: vectors.into_iter(), dimensions }
+  }
+}
+
+impl<K> Iterator for LoadedVectors<K> {
+  type Item = KeyedVector<K>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.vectors.next()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.vectors.size_hint()
+  }
+}
+
+impl<K> ExactSizeIterator for LoadedVectors<K> {}
+
+impl<K> VectorProvider<K> for LoadedVectors<K> {
+  fn vector_dimensions(&self) -> u16 {
+    self.dimensions
+  }
+}
+
+/// Parses keyed vectors one record at a time, so they can either be
+/// collected up front or streamed into an index.
+trait RecordSource<K> {
+  /// The next record as its number, key and vector, or `None` at the end of
+  /// the input.
+  fn next_record(&mut self) -> Result<Option<(usize, K, Vec<f32>)>, ProviderError>;
+
+  /// The dimensions, if the format declares them before the first record.
+  fn dimensions(&self) -> Option<u16> {
+    None
+  }
+
+  /// Bounds on the number of records left.
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    (0, None)
+  }
+}
+
+/// Read every record of `source` up front.
+fn collect<K, S>(mut source: S) -> Result<LoadedVectors<K>, ProviderError>
+  where S: RecordSource<K> {
+  let (capacity, _) = source.size_hint();
+  let mut collector = VectorCollector::new(source.dimensions(), capacity);
+  while let Some((record, key, vector)) = source.next_record()? {
+    collector.push(record, key, vector)?;
+  }
+  Ok(collector.finish())
+}
+
+/// The most records preallocated for, whatever a source's size hint says.
+/// Beyond it storage grows as records are read.
+const MAX_PREALLOCATED_RECORDS: usize = 1 << 20;
+
+/// Collects records while checking they all have the same dimensions.
+struct VectorCollector<K> {
+  vectors: Vec<KeyedVector<K>>,
+  dimensions: Option<u16>
+}
+
+impl<K> VectorCollector<K> {
+  fn new(dimensions: Option<u16>, capacity: usize) -> Self {
+    let capacity = capacity.min(MAX_PREALLOCATED_RECORDS);
+    VectorCollector { vectors: Vec::with_capacity(capacity), dimensions }
+  }
+
+  fn push(&mut self, record: usize, key: K, vector: Vec<f32>) -> Result<(), ProviderError> {
+    check_dimensions(&mut self.dimensions, record, vector.len())?;
+    self.vectors.push(KeyedVector::new(key, vector));
+    Ok(())
+  }
+
+  fn finish(self) -> LoadedVectors<K> {
+    LoadedVectors::new(self.vectors, self.dimensions.unwrap_or(0))
+  }
+}
+
+/// Check that `record` has the expected number of dimensions. The first
+/// record sets them if they weren't known up front.
+fn check_dimensions(dimensions: &mut Option<u16>, record: usize, received: usize)
+    -> Result<(), ProviderError> {
+  let expected = match *dimensions {
+    Some(dimensions) => dimensions as usize,
+    None => *dimensions.insert(dimensions_from(record, received)?) as usize
+  };
+  if received != expected {
+    return Err(ProviderError::DimensionMismatch { record, expected, received })
+  }
+  Ok(())
+}
+
+/// Convert a dimension count read from a file into the provider's type.
+fn dimensions_from(record: usize, dimensions: usize) -> Result<u16, ProviderError> {
+  u16::try_from(dimensions)
+    .map_err(|_| ProviderError::malformed(record, format!("{} dimensions is too many", dimensions)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The record and message of a [`ProviderError::Malformed`].
+  pub(super) fn malformed<T>(result: Result<T, ProviderError>) -> (usize, String) {
+    match result {
+      Err(ProviderError::Malformed { record, message }) => (record, message),
+      Err(e) => panic!("expected a malformed record, got {:?}", e),
+      Ok(_) => panic!("expected a malformed record")
+    }
+  }
+
+  /// The record, expected and received dimensions of a
+  /// [`ProviderError::DimensionMismatch`].
+  pub(super) fn mismatch<T>(result: Result<T, ProviderError>) -> (usize, usize, usize) {
+    match result {
+      Err(ProviderError::DimensionMismatch { record, expected, received }) => (record, expected, received),
+      Err(e) => panic!("expected a dimension mismatch, got {:?}", e),
+      Ok(_) => panic!("expected a dimension mismatch")
+    }
+  }
+
+  pub(super) fn keyed<K>(vectors: impl Iterator<Item = KeyedVector<K>>) -> Vec<(K, Vec<f32>)> {
+    vectors.map(Into::into).collect()
+  }
+
+  #[test]
+  fn preallocation_is_capped() {
+    let collector = VectorCollector::<u32>::new(None, usize::MAX);
+    assert_eq!(collector.vectors.capacity(), MAX_PREALLOCATED_RECORDS);
+  }
+
+  #[test]
+  fn first_record_sets_dimensions() {
+    let mut collector = VectorCollector::new(None, 0);
+    collector.push(1, 'a', vec![1.0, 2.0]).unwrap();
+    assert_eq!(mismatch(collector.push(2, 'b', vec![1.0])), (2, 2, 1));
+    let loaded = collector.finish();
+    assert_eq!(loaded.vector_dimensions(), 2);
+    assert_eq!(keyed(loaded), vec![('a', vec![1.0, 2.0])]);
+  }
+
+  #[test]
+  fn too_many_dimensions_are_malformed() {
+    let (record, _) = malformed(check_dimensions(&mut None, 3, 1 << 16));
+    assert_eq!(record, 3);
+  }
+}
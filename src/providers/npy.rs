@@ -0,0 +1,298 @@
+use std::{
+  fs::File,
+  io::{BufRead, BufReader, ErrorKind, Lines, Read},
+  marker::PhantomData,
+  str::FromStr
+};
+
+use super::{
+  LoadedVectors,
+  ProviderError,
+  RecordSource,
+  VectorStream,
+  collect,
+  dimensions_from
+};
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// Read a little-endian `float32` `.npy` matrix holding one vector per row.
+/// Row `i` is keyed by line `i` of `keys`, so `keys` must have exactly one
+/// line per row.
+pub fn read<K, M, Ks>(matrix: M, keys: Ks) -> Result<LoadedVectors<K>, ProviderError>
+  where K: FromStr,
+        M: Read,
+        Ks: BufRead {
+  collect(NpyRows::new(matrix, keys, None)?)
+}
+
+/// Like [`read`], also checking the header's shape against the file's
+/// length.
+pub fn read_file<K, Ks>(matrix: File, keys: Ks) -> Result<LoadedVectors<K>, ProviderError>
+  where K: FromStr,
+        Ks: BufRead {
+  let len = matrix.metadata()?.len();
+  collect(NpyRows::new(BufReader::new(matrix), keys, Some(len))?)
+}
+
+/// Stream a `.npy` matrix, decoding rows as they are consumed. The header
+/// is read up front.
+pub fn stream<K, M, Ks>(matrix: M, keys: Ks) -> Result<VectorStream<K>, ProviderError>
+  where K: FromStr + 'static,
+        M: Read + 'static,
+        Ks: BufRead + 'static {
+  VectorStream::new(NpyRows::new(matrix, keys, None)?)
+}
+
+/// Like [`stream`], also checking the header's shape against the file's
+/// length.
+pub fn stream_file<K, Ks>(matrix: File, keys: Ks) -> Result<VectorStream<K>, ProviderError>
+  where K: FromStr + 'static,
+        Ks: BufRead + 'static {
+  let len = matrix.metadata()?.len();
+  VectorStream::new(NpyRows::new(BufReader::new(matrix), keys, Some(len))?)
+}
+
+struct NpyRows<K, M, Ks> {
+  matrix: M,
+  keys: Lines<Ks>,
+  n_rows: usize,
+  /// whether `n_rows` was checked against the file's length, so it can be
+  /// trusted as a size hint
+  checked_len: bool,
+  dimensions: u16,
+  /// the zero-based index of the next row
+  row: usize,
+  buf: Vec<u8>,
+  key: PhantomData<fn() -> K>
+}
+
+impl<K, M: Read, Ks: BufRead> NpyRows<K, M, Ks> {
+  /// Read the header of `matrix`, checking its shape against the length of
+  /// the whole file if known.
+  fn new(mut matrix: M, keys: Ks, len: Option<u64>) -> Result<Self, ProviderError> {
+    let (n_rows, dims) = read_header(&mut matrix, len)?;
+    Ok(NpyRows {
+      matrix,
+      keys: keys.lines(),
+      n_rows,
+      checked_len: len.is_some(),
+      dimensions: dimensions_from(1, dims)?,
+      row: 0,
+      buf: vec![0u8; dims * 4],
+      key: PhantomData
+    })
+  }
+}
+
+impl<K: FromStr, M: Read, Ks: BufRead> RecordSource<K> for NpyRows<K, M, Ks> {
+  fn next_record(&mut self) -> Result<Option<(usize, K, Vec<f32>)>, ProviderError> {
+    let record = self.row + 1;
+    if self.row == self.n_rows {
+      if self.keys.any(|line| line.is_ok_and(|line| !line.trim().is_empty())) {
+        return Err(ProviderError::malformed(record, "key file has more keys than the matrix has rows"))
+      }
+      return Ok(None)
+    }
+    let key = self.keys.next()
+      .ok_or_else(|| ProviderError::malformed(record, "no key for row in key file"))??;
+    let key = key.trim()
+      .parse()
+      .map_err(|_| ProviderError::malformed(record, "invalid key in key file"))?;
+    self.matrix.read_exact(&mut self.buf).map_err(|e| match e.kind() {
+      ErrorKind::UnexpectedEof => ProviderError::malformed(record, "truncated row"),
+      _ => ProviderError::Io(e)
+    })?;
+    let vector = self.buf.chunks_exact(4)
+      .map(|bytes| f32::from_le_bytes(bytes.try_into().expect("4 byte component")))
+      .collect();
+    self.row += 1;
+    Ok(Some((record, key, vector)))
+  }
+
+  fn dimensions(&self) -> Option<u16> {
+    Some(self.dimensions)
+  }
+
+  /// An unchecked header may claim more rows than there are, so it only
+  /// bounds the rows from above.
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = self.n_rows - self.row;
+    if self.checked_len {
+      (remaining, Some(remaining))
+    } else {
+      (0, Some(remaining))
+    }
+  }
+}
+
+/// Read the header up to the start of the data, returning the matrix shape.
+/// If the file is `len` bytes long, the shape must account for all of the
+/// data.
+fn read_header<M: Read>(matrix: &mut M, len: Option<u64>) -> Result<(usize, usize), ProviderError> {
+  let mut preamble = [0u8; 8];
+  matrix.read_exact(&mut preamble)?;
+  if &preamble[..6] != MAGIC {
+    return Err(ProviderError::InvalidHeader("not an npy file".to_string()))
+  }
+  let (header_len, preamble_len) = match preamble[6] {
+    1 => {
+      let mut len = [0u8; 2];
+      matrix.read_exact(&mut len)?;
+      (u16::from_le_bytes(len) as usize, 10)
+    },
+    2 | 3 => {
+      let mut len = [0u8; 4];
+      matrix.read_exact(&mut len)?;
+      (u32::from_le_bytes(len) as usize, 12)
+    },
+    version => return Err(ProviderError::InvalidHeader(format!("unsupported version {}", version)))
+  };
+  let mut header = vec![0u8; header_len];
+  matrix.read_exact(&mut header)?;
+  let header = String::from_utf8_lossy(&header);
+  let descr = header_value(&header, "descr")
+    .map(|value| value.trim_matches(|c| c == '\'' || c == '"'));
+  if descr != Some("<f4") {
+    return Err(ProviderError::InvalidHeader(format!(
+      "expected little-endian float32 data, found {}", descr.unwrap_or("none")
+    )))
+  }
+  if header_value(&header, "fortran_order") != Some("False") {
+    return Err(ProviderError::InvalidHeader("fortran ordered data isn't supported".to_string()))
+  }
+  let shape = header.split_once("'shape':")
+    .and_then(|(_, rest)| rest.split_once('('))
+    .and_then(|(_, rest)| rest.split_once(')'))
+    .map(|(shape, _)| shape)
+    .ok_or_else(|| ProviderError::InvalidHeader("missing shape".to_string()))?
+    .split(',')
+    .map(str::trim)
+    .filter(|dim| !dim.is_empty())
+    .map(|dim| dim.parse::<usize>())
+    .collect::<Result<Vec<usize>, _>>()
+    .map_err(|e| ProviderError::InvalidHeader(format!("invalid shape: {}", e)))?;
+  let [n_rows, dims] = shape[..] else {
+    return Err(ProviderError::InvalidHeader(format!("expected a matrix, found shape {:?}", shape)))
+  };
+  let data_len = (n_rows as u64).checked_mul(dims as u64)
+    .and_then(|n_values| n_values.checked_mul(4))
+    .ok_or_else(|| ProviderError::InvalidHeader(format!("shape ({}, {}) is too large", n_rows, dims)))?;
+  if let Some(len) = len {
+    let found = len.saturating_sub(preamble_len + header_len as u64);
+    if found != data_len {
+      return Err(ProviderError::InvalidHeader(format!(
+        "shape ({}, {}) needs {} bytes of data, found {}", n_rows, dims, data_len, found
+      )))
+    }
+  }
+  Ok((n_rows, dims))
+}
+
+/// The raw value of a scalar `key` in the header dict.
+fn header_value<'h>(header: &'h str, key: &str) -> Option<&'h str> {
+  let (_, rest) = header.split_once(&format!("'{}':", key))?;
+  rest.split(',').next().map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, io::Write, process};
+
+  use super::*;
+  use crate::{
+    VectorProvider,
+    providers::tests::{keyed, malformed}
+  };
+
+  fn npy(version: u8, header: &str, data: &[f32]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend([version, 0]);
+    match version {
+      1 => bytes.extend((header.len() as u16).to_le_bytes()),
+      _ => bytes.extend((header.len() as u32).to_le_bytes())
+    }
+    bytes.extend(header.as_bytes());
+    bytes.extend(data.iter().flat_map(|value| value.to_le_bytes()));
+    bytes
+  }
+
+  fn matrix(shape: &str) -> String {
+    format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}\n", shape)
+  }
+
+  fn header_error<T>(result: Result<T, ProviderError>) -> String {
+    match result {
+      Err(ProviderError::InvalidHeader(message)) => message,
+      Err(e) => panic!("expected an invalid header, got {:?}", e),
+      Ok(_) => panic!("expected an invalid header")
+    }
+  }
+
+  #[test]
+  fn rows_are_keyed_by_line() {
+    for version in [1, 2] {
+      let bytes = npy(version, &matrix("(2, 3)"), &[1.0, 2.0, 3.0, 4.0, 5.0, 6.5]);
+      let loaded = read::<String, _, _>(&bytes[..], "a\n b \n".as_bytes()).unwrap();
+      assert_eq!(loaded.vector_dimensions(), 3);
+      assert_eq!(keyed(loaded), vec![
+        ("a".to_string(), vec![1.0, 2.0, 3.0]),
+        ("b".to_string(), vec![4.0, 5.0, 6.5])
+      ]);
+    }
+  }
+
+  #[test]
+  fn headers_must_describe_a_float32_matrix() {
+    let read_header = |bytes: Vec<u8>| header_error(read::<u32, _, _>(&bytes[..], "".as_bytes()));
+    let mut bytes = npy(1, &matrix("(1, 1)"), &[1.0]);
+    bytes[1] = b'X';
+    assert_eq!(read_header(bytes), "not an npy file");
+    assert_eq!(read_header(npy(4, &matrix("(1, 1)"), &[1.0])), "unsupported version 4");
+    let f8 = "{'descr': '<f8', 'fortran_order': False, 'shape': (1, 1), }";
+    assert_eq!(read_header(npy(1, f8, &[])), "expected little-endian float32 data, found <f8");
+    let fortran = "{'descr': '<f4', 'fortran_order': True, 'shape': (1, 1), }";
+    assert_eq!(read_header(npy(1, fortran, &[])), "fortran ordered data isn't supported");
+    assert_eq!(read_header(npy(1, &matrix("(3,)"), &[])), "expected a matrix, found shape [3]");
+    assert!(read_header(npy(1, &matrix("(1, x)"), &[])).starts_with("invalid shape"));
+    let huge = format!("({}, 2)", usize::MAX);
+    assert!(read_header(npy(1, &matrix(&huge), &[])).ends_with("is too large"));
+  }
+
+  #[test]
+  fn keys_must_match_rows() {
+    let bytes = npy(1, &matrix("(2, 1)"), &[1.0, 2.0]);
+    assert_eq!(
+      malformed(read::<u32, _, _>(&bytes[..], "1\n".as_bytes())),
+      (2, "no key for row in key file".to_string())
+    );
+    assert_eq!(
+      malformed(read::<u32, _, _>(&bytes[..], "1\n2\n3\n".as_bytes())),
+      (3, "key file has more keys than the matrix has rows".to_string())
+    );
+    assert_eq!(
+      malformed(read::<u32, _, _>(&bytes[..], "1\nx\n".as_bytes())),
+      (2, "invalid key in key file".to_string())
+    );
+    // trailing blank lines aren't keys
+    assert_eq!(read::<u32, _, _>(&bytes[..], "1\n2\n\n".as_bytes()).unwrap().len(), 2);
+  }
+
+  #[test]
+  fn unchecked_shapes_fail_at_the_truncated_row() {
+    let bytes = npy(1, &matrix("(3, 2)"), &[1.0, 2.0, 3.0]);
+    let result = read::<u32, _, _>(&bytes[..], "1\n2\n3\n".as_bytes());
+    assert_eq!(malformed(result), (2, "truncated row".to_string()));
+  }
+
+  #[test]
+  fn file_shapes_are_checked_against_the_length() {
+    let path = env::temp_dir().join(format!("recommender-npy-test-{}.npy", process::id()));
+    fs::File::create(&path).unwrap()
+      .write_all(&npy(1, &matrix("(3, 2)"), &[1.0, 2.0, 3.0, 4.0]))
+      .unwrap();
+    let result = read_file::<u32, _>(fs::File::open(&path).unwrap(), "1\n2\n3\n".as_bytes());
+    fs::remove_file(&path).unwrap();
+    assert_eq!(header_error(result), "shape (3, 2) needs 24 bytes of data, found 16");
+  }
+}
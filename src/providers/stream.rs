@@ -0,0 +1,169 @@
+use std::sync::{
+  Arc,
+  Mutex,
+  PoisonError
+};
+
+use super::{
+  KeyedVector,
+  ProviderError,
+  RecordSource,
+  VectorProvider,
+  check_dimensions
+};
+
+/// Vectors parsed lazily as an index is built from them, so large inputs,
+/// pipes and decompressing readers don't have to be counted or buffered
+/// first. Only the first record is read up front, to learn the dimensions.
+///
+/// The stream ends at the first invalid record. Since an index build can't
+/// observe that, the error is kept in the stream's [`StreamStatus`], which
+/// should be checked once the build is done.
+pub struct VectorStream<K> {
+  source: Box<dyn RecordSource<K>>,
+  /// the record read ahead of the iterator
+  next: Option<KeyedVector<K>>,
+  dimensions: u16,
+  status: StreamStatus
+}
+
+impl<K> VectorStream<K> {
+  pub(super) fn new<S>(mut source: S) -> Result<Self, ProviderError>
+    where S: RecordSource<K> + 'static {
+    let mut dimensions = source.dimensions();
+    let next = match source.next_record()? {
+      Some((record, key, vector)) => {
+        check_dimensions(&mut dimensions, record, vector.len())?;
+        Some(KeyedVector::new(key, vector))
+      },
+      None => None
+    };
+    Ok(VectorStream {
+      source: Box::new(source),
+      next,
+      dimensions: dimensions.unwrap_or(0),
+      status: StreamStatus::default()
+    })
+  }
+
+  /// A handle on the outcome of reading the stream, which stays usable
+  /// after the stream is consumed.
+  pub fn status(&self) -> StreamStatus {
+    self.status.clone()
+  }
+
+  fn read_ahead(&mut self) -> Result<Option<KeyedVector<K>>, ProviderError> {
+    match self.source.next_record()? {
+      Some((record, key, vector)) => {
+        let mut dimensions = Some(self.dimensions);
+        check_dimensions(&mut dimensions, record, vector.len())?;
+        Ok(Some(KeyedVector::new(key, vector)))
+      },
+      None => Ok(None)
+    }
+  }
+}
+
+impl<K> Iterator for VectorStream<K> {
+  type Item = KeyedVector<K>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let current = self.next.take()?;
+    self.next = self.read_ahead().unwrap_or_else(|e| {
+      self.status.fail(e);
+      None
+    });
+    Some(current)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    if self.next.is_none() {
+      return (0, Some(0))
+    }
+    let (lower, upper) = self.source.size_hint();
+    (lower.saturating_add(1), upper.and_then(|upper| upper.checked_add(1)))
+  }
+}
+
+impl<K> VectorProvider<K> for VectorStream<K> {
+  fn vector_dimensions(&self) -> u16 {
+    self.dimensions
+  }
+}
+
+/// Whether a [`VectorStream`] was read to the end, or stopped at an invalid
+/// record.
+#[derive(Debug, Clone, Default)]
+pub struct StreamStatus {
+  error: Arc<Mutex<Option<ProviderError>>>
+}
+
+impl StreamStatus {
+  /// Take the error the stream stopped at, if any. Anything built from the
+  /// stream before that is missing the remaining vectors.
+  pub fn check(&self) -> Result<(), ProviderError> {
+    match self.error.lock().unwrap_or_else(PoisonError::into_inner).take() {
+      Some(e) => Err(e),
+      None => Ok(())
+    }
+  }
+
+  fn fail(&self, e: ProviderError) {
+    *self.error.lock().unwrap_or_else(PoisonError::into_inner) = Some(e);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+  use crate::providers::{
+    DelimitedFormat,
+    delimited,
+    tests::{keyed, malformed, mismatch},
+    vecs
+  };
+
+  #[test]
+  fn streams_read_one_record_ahead() {
+    let stream = delimited::stream::<u32, _>(Cursor::new("1,1,2\n2,3,4\n"), DelimitedFormat::csv()).unwrap();
+    let status = stream.status();
+    assert_eq!(stream.vector_dimensions(), 2);
+    assert_eq!(stream.size_hint(), (1, None));
+    assert_eq!(keyed(stream), vec![(1, vec![1.0, 2.0]), (2, vec![3.0, 4.0])]);
+    assert!(status.check().is_ok());
+  }
+
+  #[test]
+  fn status_keeps_the_error_the_stream_stopped_at() {
+    let mut bytes = Vec::new();
+    for record in [[1f32, 2.0], [3.0, 4.0]] {
+      bytes.extend(2i32.to_le_bytes());
+      bytes.extend(record.iter().flat_map(|value| value.to_le_bytes()));
+    }
+    bytes.extend(2i32.to_le_bytes());
+    let stream = vecs::stream_fvecs::<u32, _>(Cursor::new(bytes)).unwrap();
+    let status = stream.status();
+    assert_eq!(keyed(stream), vec![(0, vec![1.0, 2.0]), (1, vec![3.0, 4.0])]);
+    assert_eq!(malformed(status.check()), (3, "truncated record".to_string()));
+    // the error is taken by the first check
+    assert!(status.check().is_ok());
+  }
+
+  #[test]
+  fn later_records_must_match_the_first() {
+    let stream = delimited::stream::<u32, _>(Cursor::new("1,1,2\n2,3\n"), DelimitedFormat::csv()).unwrap();
+    let status = stream.status();
+    assert_eq!(stream.count(), 1);
+    assert_eq!(mismatch(status.check()), (2, 2, 1));
+  }
+
+  #[test]
+  fn first_record_errors_are_returned_up_front() {
+    let result = delimited::stream::<u32, _>(Cursor::new("x,1\n"), DelimitedFormat::csv());
+    assert_eq!(malformed(result), (1, "invalid key".to_string()));
+    let stream = delimited::stream::<u32, _>(Cursor::new(""), DelimitedFormat::csv()).unwrap();
+    assert_eq!((stream.vector_dimensions(), stream.size_hint()), (0, (0, Some(0))));
+  }
+}
@@ -0,0 +1,169 @@
+use std::{
+  io::{ErrorKind, Read},
+  marker::PhantomData
+};
+
+use super::{
+  LoadedVectors,
+  ProviderError,
+  RecordSource,
+  VectorStream,
+  collect,
+  dimensions_from
+};
+
+/// Read an `.fvecs` file, where each record is a little-endian `i32`
+/// dimension count followed by that many `f32` components. Records are
+/// keyed by their zero-based row.
+pub fn read_fvecs<K, Rd>(input: Rd) -> Result<LoadedVectors<K>, ProviderError>
+  where K: TryFrom<usize>,
+        Rd: Read {
+  collect(VecsRecords::new(input, 4, decode_f32))
+}
+
+/// Read a `.bvecs` file, where each record is a little-endian `i32`
+/// dimension count followed by that many `u8` components. Records are keyed
+/// by their zero-based row.
+pub fn read_bvecs<K, Rd>(input: Rd) -> Result<LoadedVectors<K>, ProviderError>
+  where K: TryFrom<usize>,
+        Rd: Read {
+  collect(VecsRecords::new(input, 1, decode_u8))
+}
+
+/// Stream an `.fvecs` file, decoding records as they are consumed.
+pub fn stream_fvecs<K, Rd>(input: Rd) -> Result<VectorStream<K>, ProviderError>
+  where K: TryFrom<usize> + 'static,
+        Rd: Read + 'static {
+  VectorStream::new(VecsRecords::new(input, 4, decode_f32))
+}
+
+/// Stream a `.bvecs` file, decoding records as they are consumed.
+pub fn stream_bvecs<K, Rd>(input: Rd) -> Result<VectorStream<K>, ProviderError>
+  where K: TryFrom<usize> + 'static,
+        Rd: Read + 'static {
+  VectorStream::new(VecsRecords::new(input, 1, decode_u8))
+}
+
+fn decode_f32(bytes: &[u8]) -> f32 {
+  f32::from_le_bytes(bytes.try_into().expect("4 byte component"))
+}
+
+fn decode_u8(bytes: &[u8]) -> f32 {
+  bytes[0] as f32
+}
+
+struct VecsRecords<K, Rd> {
+  input: Rd,
+  component_size: usize,
+  decode: fn(&[u8]) -> f32,
+  /// the zero-based row of the next record
+  row: usize,
+  buf: Vec<u8>,
+  key: PhantomData<fn() -> K>
+}
+
+impl<K, Rd: Read> VecsRecords<K, Rd> {
+  fn new(input: Rd, component_size: usize, decode: fn(&[u8]) -> f32) -> Self {
+    VecsRecords { input, component_size, decode, row: 0, buf: Vec::new(), key: PhantomData }
+  }
+}
+
+impl<K: TryFrom<usize>, Rd: Read> RecordSource<K> for VecsRecords<K, Rd> {
+  fn next_record(&mut self) -> Result<Option<(usize, K, Vec<f32>)>, ProviderError> {
+    let row = self.row;
+    let record = row + 1;
+    let Some(dimensions) = read_dimensions(&mut self.input, record)? else {
+      return Ok(None)
+    };
+    self.buf.resize(dimensions * self.component_size, 0);
+    self.input.read_exact(&mut self.buf).map_err(|e| match e.kind() {
+      ErrorKind::UnexpectedEof => ProviderError::malformed(record, "truncated record"),
+      _ => ProviderError::Io(e)
+    })?;
+    let vector = self.buf.chunks_exact(self.component_size)
+      .map(self.decode)
+      .collect();
+    let key = K::try_from(row)
+      .map_err(|_| ProviderError::malformed(record, "row number doesn't fit the key type"))?;
+    self.row += 1;
+    Ok(Some((record, key, vector)))
+  }
+}
+
+/// Read the dimension count starting a record, or `None` at the end of the
+/// input.
+fn read_dimensions<Rd: Read>(input: &mut Rd, record: usize) -> Result<Option<usize>, ProviderError> {
+  let mut buf = [0u8; 4];
+  let mut filled = 0;
+  while filled < buf.len() {
+    match input.read(&mut buf[filled..]) {
+      Ok(0) if filled == 0 => return Ok(None),
+      Ok(0) => return Err(ProviderError::malformed(record, "truncated record")),
+      Ok(n) => filled += n,
+      Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+      Err(e) => return Err(ProviderError::Io(e))
+    }
+  }
+  let dimensions = i32::from_le_bytes(buf);
+  let dimensions = usize::try_from(dimensions)
+    .map_err(|_| ProviderError::malformed(record, format!("negative dimension count {}", dimensions)))?;
+  dimensions_from(record, dimensions)?;
+  Ok(Some(dimensions))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    VectorProvider,
+    providers::tests::{keyed, malformed, mismatch}
+  };
+
+  fn fvecs(records: &[&[f32]]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for record in records {
+      bytes.extend((record.len() as i32).to_le_bytes());
+      bytes.extend(record.iter().flat_map(|value| value.to_le_bytes()));
+    }
+    bytes
+  }
+
+  #[test]
+  fn fvecs_are_keyed_by_row() {
+    let loaded = read_fvecs::<u32, _>(&fvecs(&[&[1.0, 2.0], &[3.0, 4.5]])[..]).unwrap();
+    assert_eq!(loaded.vector_dimensions(), 2);
+    assert_eq!(keyed(loaded), vec![(0, vec![1.0, 2.0]), (1, vec![3.0, 4.5])]);
+  }
+
+  #[test]
+  fn bvecs_components_are_bytes() {
+    let bytes = [2, 0, 0, 0, 7, 255, 2, 0, 0, 0, 1];
+    let (record, _) = malformed(read_bvecs::<u32, _>(&bytes[..]));
+    assert_eq!(record, 2);
+    let loaded = read_bvecs::<u32, _>(&bytes[..6]).unwrap();
+    assert_eq!(keyed(loaded), vec![(0, vec![7.0, 255.0])]);
+  }
+
+  #[test]
+  fn truncated_records_are_malformed() {
+    let bytes = fvecs(&[&[1.0, 2.0], &[3.0, 4.0]]);
+    // cut inside the second record's components, then inside its count
+    assert_eq!(malformed(read_fvecs::<u32, _>(&bytes[..bytes.len() - 1])), (2, "truncated record".to_string()));
+    assert_eq!(malformed(read_fvecs::<u32, _>(&bytes[..14])), (2, "truncated record".to_string()));
+  }
+
+  #[test]
+  fn negative_and_mismatched_dimensions_are_rejected() {
+    let (record, message) = malformed(read_fvecs::<u32, _>(&(-1i32).to_le_bytes()[..]));
+    assert_eq!((record, message.as_str()), (1, "negative dimension count -1"));
+    let bytes = fvecs(&[&[1.0, 2.0], &[3.0]]);
+    assert_eq!(mismatch(read_fvecs::<u32, _>(&bytes[..])), (2, 2, 1));
+  }
+
+  #[test]
+  fn rows_must_fit_the_key_type() {
+    let records = vec![&[0f32][..]; 257];
+    let (record, _) = malformed(read_fvecs::<u8, _>(&fvecs(&records)[..]));
+    assert_eq!(record, 257);
+  }
+}
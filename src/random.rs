@@ -1,6 +1,10 @@
-use std::iter::repeat_with;
+use std::hash::{Hash, Hasher};
 
-use rand::prelude::Rng;
+use rand::{
+  prelude::Rng,
+  SeedableRng,
+  rngs::StdRng
+};
 
 use super::{
   Recommender,
@@ -40,14 +44,133 @@ impl<Provider, Key, Rec> Recommender<Key, Rec> for RandomRecommender<Provider, R
   fn recommend(&self, _subject_id: &Key, n_recommendations: u16)
     -> Result<RecommendationList<Rec>, RecommendError> {
     let mut rng = rand::thread_rng();
-    if rng.gen::<f32>() < self.empty_rate {
-      return Err(RecommendError::NotFound)
+    random_recommendations(&mut rng, self.empty_rate, n_recommendations, |_| (self.id_provider)())
+  }
+}
+
+/// A [`RandomRecommender`] that always responds the same to the same
+/// subject, whatever the order of calls. Each call's RNG is derived from
+/// the seed and the subject's hash, and is passed to `id_provider` to draw
+/// the recommended IDs from.
+///
+/// Subjects are hashed with FNV-1a rather than the standard hasher, whose
+/// output may change between Rust releases. Responses can still differ
+/// between `rand` releases, and between platforms for keys whose hash
+/// depends on endianness or pointer width, such as integers.
+#[derive(Builder)]
+pub struct SeededRandomRecommender<Provider, Rec>
+  where Provider: Fn(&mut StdRng) -> Rec {
+  id_provider: Provider,
+  #[builder(default = "0.2")]
+  empty_rate: f32,
+  seed: u64
+}
+
+impl<Provider, Rec> SeededRandomRecommender<Provider, Rec>
+  where Provider: Fn(&mut StdRng) -> Rec + Clone,
+        Rec: Clone {
+  pub fn builder() -> SeededRandomRecommenderBuilder<Provider, Rec> {
+    SeededRandomRecommenderBuilder::default()
+  }
+}
+
+impl<Provider, Rec> SeededRandomRecommender<Provider, Rec>
+  where Provider: Fn(&mut StdRng) -> Rec {
+  pub fn new(id_provider: Provider, empty_rate: f32, seed: u64) -> Self {
+    Self { id_provider, empty_rate, seed }
+  }
+}
+
+impl<Provider, Key, Rec> Recommender<Key, Rec> for SeededRandomRecommender<Provider, Rec>
+  where Provider: Fn(&mut StdRng) -> Rec,
+        Key: Hash,
+        Rec: PartialEq {
+  fn recommend(&self, subject_id: &Key, n_recommendations: u16)
+    -> Result<RecommendationList<Rec>, RecommendError> {
+    let mut hasher = Fnv1a::default();
+    (self.seed, subject_id).hash(&mut hasher);
+    let mut rng = StdRng::seed_from_u64(hasher.finish());
+    random_recommendations(&mut rng, self.empty_rate, n_recommendations, &self.id_provider)
+  }
+}
+
+/// Respond not found with probability `empty_rate`, or else with
+/// `n_recommendations` IDs drawn by `id_provider` and random scores.
+fn random_recommendations<R, Rec>(
+  rng: &mut R, empty_rate: f32, n_recommendations: u16, id_provider: impl Fn(&mut R) -> Rec
+) -> Result<RecommendationList<Rec>, RecommendError>
+  where R: Rng,
+        Rec: PartialEq {
+  if rng.gen::<f32>() < empty_rate {
+    return Err(RecommendError::NotFound)
+  }
+  let recs = (0..n_recommendations)
+    .map(|_| {
+      let item_id = id_provider(rng);
+      Recommendation::new(item_id, rng.gen::<f32>())
+    });
+  Ok(RecommendationList::from_iter_with_sort(recs))
+}
+
+/// The 64-bit FNV-1a hash, which unlike the standard hasher is fixed.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+  fn default() -> Self {
+    Fnv1a(0xcbf29ce484222325)
+  }
+}
+
+impl Hasher for Fnv1a {
+  fn finish(&self) -> u64 {
+    self.0
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for byte in bytes {
+      self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
     }
-    let n_recs = n_recommendations as usize;
-    let recs = repeat_with(|| (self.id_provider)())
-      .zip(repeat_with(move || rng.gen::<f32>()))
-      .map(Recommendation::from)
-      .take(n_recs);
-    Ok(RecommendationList::from_iter_with_sort(recs))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn recommender() -> SeededRandomRecommender<impl Fn(&mut StdRng) -> u32 + Clone, u32> {
+    SeededRandomRecommender::builder()
+      .id_provider(|rng: &mut StdRng| rng.gen_range(0..1000))
+      .empty_rate(0.0)
+      .seed(42)
+      .build()
+      .unwrap()
+  }
+
+  fn items(recs: Result<RecommendationList<u32>, RecommendError>) -> Vec<(u32, f32)> {
+    recs.unwrap().0.iter().map(|rec| (rec.item_id, rec.score)).collect()
+  }
+
+  #[test]
+  fn seeded_responses_ignore_call_order() {
+    let (first, second) = (recommender(), recommender());
+    let a = items(first.recommend(&1u64, 5));
+    let b = items(first.recommend(&2u64, 5));
+    assert_eq!(items(second.recommend(&2u64, 5)), b);
+    assert_eq!(items(second.recommend(&1u64, 5)), a);
+    assert_ne!(a, b);
+  }
+
+  #[test]
+  fn seeded_ids_come_from_the_derived_rng() {
+    let recs = items(recommender().recommend(&1u64, 5));
+    assert_eq!(recs.len(), 5);
+    assert!(recs.windows(2).any(|pair| pair[0].0 != pair[1].0));
+  }
+
+  #[test]
+  fn fnv1a_matches_reference_values() {
+    let mut hasher = Fnv1a::default();
+    hasher.write(b"a");
+    assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
   }
 }
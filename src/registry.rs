@@ -0,0 +1,74 @@
+use std::{
+  collections::HashMap,
+  sync::Arc
+};
+
+use super::{
+  DynRecommender,
+  Recommender
+};
+
+/// Recommenders of any backend looked up by name, e.g. an index per catalog,
+/// or an approximate index next to an exact one to compare against.
+pub struct RecommenderRegistry<K, R> {
+  recommenders: HashMap<String, Arc<DynRecommender<K, R>>>
+}
+
+impl<K, R> Default for RecommenderRegistry<K, R> {
+  fn default() -> Self {
+    RecommenderRegistry { recommenders: HashMap::new() }
+  }
+}
+
+impl<K, R> RecommenderRegistry<K, R> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Register `recommender` as `name`, returning the recommender it
+  /// replaces.
+  pub fn register<Rc>(&mut self, name: impl Into<String>, recommender: Rc)
+      -> Option<Arc<DynRecommender<K, R>>>
+    where Rc: Recommender<K, R> + Send + Sync + 'static {
+    self.register_shared(name, Arc::new(recommender))
+  }
+
+  /// Register a recommender that's already boxed, e.g. one built from a
+  /// [`RecommenderConfig`](crate::config::RecommenderConfig).
+  pub fn register_boxed(&mut self, name: impl Into<String>, recommender: Box<DynRecommender<K, R>>)
+      -> Option<Arc<DynRecommender<K, R>>> {
+    self.register_shared(name, Arc::from(recommender))
+  }
+
+  pub fn register_shared(&mut self, name: impl Into<String>, recommender: Arc<DynRecommender<K, R>>)
+      -> Option<Arc<DynRecommender<K, R>>> {
+    self.recommenders.insert(name.into(), recommender)
+  }
+
+  pub fn with<Rc>(mut self, name: impl Into<String>, recommender: Rc) -> Self
+    where Rc: Recommender<K, R> + Send + Sync + 'static {
+    self.register(name, recommender);
+    self
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Arc<DynRecommender<K, R>>> {
+    self.recommenders.get(name)
+  }
+
+  pub fn remove(&mut self, name: &str) -> Option<Arc<DynRecommender<K, R>>> {
+    self.recommenders.remove(name)
+  }
+
+  /// The registered names, in no particular order.
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.recommenders.keys().map(String::as_str)
+  }
+
+  pub fn len(&self) -> usize {
+    self.recommenders.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.recommenders.is_empty()
+  }
+}
@@ -0,0 +1,161 @@
+use std::{
+  collections::HashSet,
+  hash::Hash,
+  num::NonZeroUsize
+};
+
+use super::{
+  CandidateFilter,
+  Recommendation,
+  RecommendationList,
+  RecommendError,
+  filter
+};
+
+/// What to recommend for.
+#[derive(Debug)]
+pub enum Subject<'a, K> {
+  /// An item stored in the recommender. It's never recommended for itself.
+  Item(&'a K),
+  /// An arbitrary query vector, such as a user embedding.
+  Vector(&'a [f32])
+}
+
+// Subjects only borrow, so they're copyable whatever the key type.
+impl<K> Clone for Subject<'_, K> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<K> Copy for Subject<'_, K> {}
+
+/// Backend search parameters that can be set per request. Backends ignore
+/// the parameters that don't apply to them, and fall back to their own
+/// defaults for unset ones.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchParams {
+  /// the number of tree nodes arroy inspects
+  pub search_k: Option<NonZeroUsize>,
+  /// the search width on the bottom layer of an hnsw graph
+  pub ef_search: Option<usize>
+}
+
+/// A recommendation query with all of its options. Only the subject and
+/// count are required, the rest is set with the `with_*` methods.
+#[derive(Debug)]
+pub struct RecommendRequest<'a, K, R> {
+  pub subject: Subject<'a, K>,
+  /// the number of recommendations to return
+  pub n_items: u16,
+  /// the number of top recommendations to skip, for paging
+  pub offset: u16,
+  pub filter: Option<&'a CandidateFilter<R>>,
+  /// items that must not be recommended, e.g. already purchased ones
+  pub exclude: Option<&'a HashSet<R>>,
+  /// the lowest score a recommendation may have; lower ones are dropped.
+  /// Backends normalize scores per metric, so a cutoff means the same on
+  /// every backend. A backend's own default cutoff applies as well.
+  pub min_score: Option<f32>,
+  pub search: SearchParams
+}
+
+impl<'a, K, R> RecommendRequest<'a, K, R> {
+  pub fn new(subject: Subject<'a, K>, n_items: u16) -> Self {
+    Self {
+      subject,
+      n_items,
+      offset: 0,
+      filter: None,
+      exclude: None,
+      min_score: None,
+      search: SearchParams::default()
+    }
+  }
+
+  pub fn item(item_id: &'a K, n_items: u16) -> Self {
+    Self::new(Subject::Item(item_id), n_items)
+  }
+
+  pub fn vector(vector: &'a [f32], n_items: u16) -> Self {
+    Self::new(Subject::Vector(vector), n_items)
+  }
+
+  pub fn with_offset(mut self, offset: u16) -> Self {
+    self.offset = offset;
+    self
+  }
+
+  pub fn with_filter(mut self, filter: &'a CandidateFilter<R>) -> Self {
+    self.filter = Some(filter);
+    self
+  }
+
+  pub fn with_exclude(mut self, exclude: &'a HashSet<R>) -> Self {
+    self.exclude = Some(exclude);
+    self
+  }
+
+  pub fn with_min_score(mut self, min_score: f32) -> Self {
+    self.min_score = Some(min_score);
+    self
+  }
+
+  pub fn with_search_params(mut self, search: SearchParams) -> Self {
+    self.search = search;
+    self
+  }
+
+  /// The number of ranked recommendations needed to fill the requested
+  /// page.
+  pub fn n_ranked(&self) -> u16 {
+    self.offset.saturating_add(self.n_items)
+  }
+
+  /// Whether the request restricts which items may be returned, beyond
+  /// leaving out the subject.
+  pub fn is_filtered(&self) -> bool {
+    self.filter.is_some() || self.exclude.is_some_and(|exclude| !exclude.is_empty())
+  }
+}
+
+impl<'a, K, R> RecommendRequest<'a, K, R>
+  where R: Eq + Hash {
+  /// Whether `item` passes the request's filter and exclusion set.
+  pub fn accepts(&self, item: &R) -> bool {
+    self.exclude.is_none_or(|exclude| !exclude.contains(item))
+      && self.filter.is_none_or(|filter| filter.accepts(item))
+  }
+
+  fn accepts_recommendation(&self, rec: &Recommendation<R>) -> bool {
+    self.min_score.is_none_or(|min_score| rec.score >= min_score)
+      && self.accepts(&rec.item_id)
+  }
+
+  /// Apply the request to recommendations ranked by a backend: drop the
+  /// ones it doesn't accept, then cut out the requested page.
+  pub fn finish(&self, recs: RecommendationList<R>) -> RecommendationList<R> {
+    let RecommendationList(mut recs) = recs;
+    recs.retain(|rec| self.accepts_recommendation(rec));
+    self.page(recs)
+  }
+
+  /// Serve the request from a source that can only return the top `n`
+  /// recommendations, fetching more of them until the page can be filled.
+  pub fn overfetch<F>(&self, fetch: F) -> Result<RecommendationList<R>, RecommendError>
+    where F: FnMut(u16) -> Result<RecommendationList<R>, RecommendError> {
+    let recs = filter::overfetch(
+      self.n_ranked(), self.min_score,
+      |rec| self.accepts_recommendation(rec),
+      fetch
+    )?;
+    Ok(self.page(recs))
+  }
+
+  fn page(&self, recs: Vec<Recommendation<R>>) -> RecommendationList<R> {
+    RecommendationList(recs.into_iter()
+      .skip(self.offset as usize)
+      .take(self.n_items as usize)
+      .collect())
+  }
+}
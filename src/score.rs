@@ -0,0 +1,149 @@
+use serde::{
+  Deserialize,
+  Serialize
+};
+use thiserror::Error;
+
+/// The families of distance functions the backends search with. Each has a
+/// canonical measure that scores are derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+  /// measured as the cosine similarity, in [-1, 1]
+  Cosine,
+  /// measured as the L2 distance
+  Euclidean,
+  /// measured as the L1 distance
+  Manhattan,
+  /// measured as the dot product
+  Dot
+}
+
+/// A backend distance function that can be turned into a score. Backends
+/// report distances in their own conventions, e.g. hnsw_rs reports
+/// `1 - cos` and arroy `(1 - cos) / 2` for cosine.
+pub trait ScoredDistance {
+  const METRIC: Metric;
+
+  /// Convert a distance reported by the backend into the canonical measure
+  /// of [`ScoredDistance::METRIC`].
+  fn canonical(distance: f32) -> f32;
+}
+
+/// How L1 and L2 distances are turned into similarities.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceTransform {
+  /// `1 / (1 + d)`, in (0, 1]
+  #[default]
+  Reciprocal,
+  /// `exp(-d / scale)`, in (0, 1]
+  Exponential { scale: Scale },
+  /// `-d`, keeping the distances' scale
+  Negate
+}
+
+impl DistanceTransform {
+  /// `exp(-d / scale)`, if `scale` is a valid [`Scale`].
+  pub fn exponential(scale: f32) -> Result<Self, InvalidScale> {
+    Ok(DistanceTransform::Exponential { scale: Scale::new(scale)? })
+  }
+
+  pub fn apply(&self, distance: f32) -> f32 {
+    match self {
+      DistanceTransform::Reciprocal => 1f32 / (1f32 + distance),
+      DistanceTransform::Exponential { scale } => (-distance / scale.get()).exp(),
+      DistanceTransform::Negate => -distance
+    }
+  }
+}
+
+/// The scale of [`DistanceTransform::Exponential`]: a finite number above
+/// zero, since any other scale would turn distances into NaN or infinite
+/// scores.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "f32", into = "f32")]
+pub struct Scale(f32);
+
+impl Scale {
+  pub fn new(scale: f32) -> Result<Self, InvalidScale> {
+    if scale.is_finite() && scale > 0f32 {
+      Ok(Scale(scale))
+    } else {
+      Err(InvalidScale(scale))
+    }
+  }
+
+  pub fn get(&self) -> f32 {
+    self.0
+  }
+}
+
+impl TryFrom<f32> for Scale {
+  type Error = InvalidScale;
+
+  fn try_from(scale: f32) -> Result<Self, Self::Error> {
+    Scale::new(scale)
+  }
+}
+
+impl From<Scale> for f32 {
+  fn from(scale: Scale) -> Self {
+    scale.0
+  }
+}
+
+#[derive(Debug, Error)]
+#[error("the exponential scale must be finite and above zero, got {0}")]
+pub struct InvalidScale(pub f32);
+
+/// How dot products are turned into scores.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DotTransform {
+  /// the dot product as is
+  #[default]
+  Raw,
+  /// the logistic function of the dot product, in (0, 1)
+  Sigmoid
+}
+
+impl DotTransform {
+  pub fn apply(&self, dot: f32) -> f32 {
+    match self {
+      DotTransform::Raw => dot,
+      DotTransform::Sigmoid => 1f32 / (1f32 + (-dot).exp())
+    }
+  }
+}
+
+/// Turns backend distances into scores where higher is better, so scores
+/// are comparable across backends using the same metric. Cosine similarity
+/// is always mapped to [0, 1]; the other metrics are configurable.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoreNormalizer {
+  pub distance: DistanceTransform,
+  pub dot: DotTransform
+}
+
+impl ScoreNormalizer {
+  pub fn new(distance: DistanceTransform, dot: DotTransform) -> Self {
+    ScoreNormalizer { distance, dot }
+  }
+
+  /// Score a distance reported for the distance function `D`.
+  pub fn score<D>(&self, distance: f32) -> f32
+    where D: ScoredDistance {
+    self.normalize(D::METRIC, D::canonical(distance))
+  }
+
+  /// Score a canonical measure of `metric`.
+  pub fn normalize(&self, metric: Metric, measure: f32) -> f32 {
+    match metric {
+      Metric::Cosine => ((1f32 + measure) / 2f32).clamp(0f32, 1f32),
+      Metric::Euclidean | Metric::Manhattan => self.distance.apply(measure),
+      Metric::Dot => self.dot.apply(measure)
+    }
+  }
+}
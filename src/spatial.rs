@@ -1,3 +1,10 @@
+use super::{
+    Recommendation,
+    RecommendationList,
+    RecommendError,
+    Recommender
+};
+
 pub struct Distance<T> {
     pub item_id: T,
     pub distance: f32,
@@ -18,7 +25,6 @@ where
     }
 }
 
-#[allow(dead_code)]
 pub trait NavigableIndex {
     type Key;
     type Point;
@@ -29,15 +35,118 @@ pub trait NavigableIndex {
         &self,
         subject: &Self::Point,
         n_items: u16,
-    ) -> impl Iterator<Item = Self::Key> {
+    ) -> Result<impl Iterator<Item = Self::Key>, RecommendError> {
         self.search(subject, n_items)
-            .into_iter()
-            .map(|dist| dist.item_id)
+            .map(|neighbors| neighbors.into_iter().map(|dist| dist.item_id))
     }
 
-    /// Get an item from the index
-    fn get_point(&self, key: &Self::Key) -> Option<Self::Point>;
+    /// Get an item from the index, or `None` if the key isn't indexed.
+    fn get_point(&self, key: &Self::Key) -> Result<Option<Self::Point>, RecommendError>;
 
     /// Return an interable of the nearest points in the space.
-    fn search(&self, subject: &Self::Point, n_items: u16) -> Self::Neighbors;
+    fn search(&self, subject: &Self::Point, n_items: u16) -> Result<Self::Neighbors, RecommendError>;
+
+    /// Convert a distance returned by [`NavigableIndex::search`] into a
+    /// recommendation score, where higher scores are better matches.
+    fn score(&self, distance: f32) -> f32 {
+        1f32 - distance
+    }
+
+    /// Neighbors scoring below this are dropped before ranking.
+    fn min_score(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// Turn the neighbors of `subject` into a ranked list of recommendations,
+/// dropping the subject itself and converting distances with
+/// [`NavigableIndex::score`]. Neighbors below [`NavigableIndex::min_score`]
+/// are left out.
+pub fn rank_neighbors<I, N, R>(index: &I, subject: Option<&I::Key>, neighbors: N) -> RecommendationList<R>
+where
+    I: NavigableIndex + ?Sized,
+    I::Key: PartialEq,
+    N: IntoIterator<Item = Distance<I::Key>>,
+    R: From<I::Key> + PartialEq,
+{
+    let min_score = index.min_score();
+    RecommendationList::from_iter_with_sort(
+        neighbors
+            .into_iter()
+            .filter(|neighbor| subject != Some(&neighbor.item_id))
+            .map(|neighbor| (neighbor.item_id, index.score(neighbor.distance)))
+            .filter(|(_, score)| min_score.is_none_or(|min_score| *score >= min_score))
+            .map(|(item_id, score)| Recommendation::new(R::from(item_id), score)),
+    )
+}
+
+/// Like [`rank_neighbors`], for the neighbors of the stored item `key`.
+/// These are searched for one more than `n_items`, since the item usually
+/// finds itself, so only the top `n_items` are kept.
+pub fn rank_item_neighbors<I, N, R>(
+    index: &I,
+    key: &I::Key,
+    neighbors: N,
+    n_items: u16,
+) -> RecommendationList<R>
+where
+    I: NavigableIndex + ?Sized,
+    I::Key: PartialEq,
+    N: IntoIterator<Item = Distance<I::Key>>,
+    R: From<I::Key> + PartialEq,
+{
+    let mut recs = rank_neighbors(index, Some(key), neighbors);
+    recs.0.truncate(n_items as usize);
+    recs
+}
+
+/// Recommend the nearest neighbors of an item stored in `index`.
+pub fn recommend_from_index<I, R>(
+    index: &I,
+    key: &I::Key,
+    n_items: u16,
+) -> Result<RecommendationList<R>, RecommendError>
+where
+    I: NavigableIndex + ?Sized,
+    I::Key: PartialEq,
+    R: From<I::Key> + PartialEq,
+{
+    let point = index.get_point(key)?.ok_or(RecommendError::NotFound)?;
+    let neighbors = index.search(&point, n_items.saturating_add(1))?;
+    Ok(rank_item_neighbors(index, key, neighbors, n_items))
+}
+
+/// Adapts any [`NavigableIndex`] into a [`Recommender`].
+pub struct IndexRecommender<I> {
+    index: I,
+}
+
+impl<I> IndexRecommender<I> {
+    pub fn new(index: I) -> Self {
+        IndexRecommender { index }
+    }
+
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+
+    pub fn into_inner(self) -> I {
+        self.index
+    }
+}
+
+impl<I, K, R> Recommender<K, R> for IndexRecommender<I>
+where
+    I: NavigableIndex,
+    I::Key: PartialEq,
+    K: TryInto<I::Key> + Clone,
+    R: From<I::Key> + PartialEq,
+{
+    fn recommend(&self, item_id: &K, n_items: u16) -> Result<RecommendationList<R>, RecommendError> {
+        let key: I::Key = item_id
+            .clone()
+            .try_into()
+            .map_err(|_| RecommendError::IncompatibleId)?;
+        recommend_from_index(&self.index, &key, n_items)
+    }
 }
@@ -0,0 +1,460 @@
+use std::hash::Hash;
+
+use serde::{
+  Deserialize,
+  Serialize
+};
+use thiserror::Error;
+
+use super::{
+  CandidateFilter,
+  KeyedVector,
+  RecommendRequest,
+  Recommender,
+  RecommendError,
+  RecommendationList,
+  Subject,
+  VectorProvider,
+  VectorRecommender
+};
+
+#[derive(Debug, Error)]
+pub enum TransformError {
+  #[error("{step} expects {expected} dimensions, receives {received}")]
+  DimensionMismatch {
+    step: &'static str,
+    expected: usize,
+    received: usize
+  },
+  #[error("provider has {received} dimensions, pipeline expects {expected}")]
+  ProviderMismatch {
+    expected: u16,
+    received: u16
+  },
+  #[error("{0} dimensions is too many")]
+  TooManyDimensions(usize)
+}
+
+/// A single preprocessing step applied to every vector.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum VectorTransform {
+  /// scale to unit L2 norm, leaving zero vectors as they are
+  Normalize,
+  /// truncate or zero-pad to `dimensions` components
+  Resize { dimensions: u16 },
+  /// subtract `mean` component-wise
+  Center { mean: Vec<f32> },
+  /// multiply by a matrix with one row per output component, e.g. PCA
+  /// components, scaled for whitening
+  Project { matrix: Vec<Vec<f32>> }
+}
+
+impl VectorTransform {
+  fn name(&self) -> &'static str {
+    match self {
+      VectorTransform::Normalize => "normalize",
+      VectorTransform::Resize { .. } => "resize",
+      VectorTransform::Center { .. } => "center",
+      VectorTransform::Project { .. } => "project"
+    }
+  }
+
+  /// The dimensions of the output for `input` dimensions, if the step
+  /// accepts them.
+  fn output_dimensions(&self, input: u16) -> Result<u16, TransformError> {
+    let expect = |expected: usize| match expected == input as usize {
+      true => Ok(()),
+      false => Err(TransformError::DimensionMismatch {
+        step: self.name(),
+        expected,
+        received: input as usize
+      })
+    };
+    match self {
+      VectorTransform::Normalize => Ok(input),
+      VectorTransform::Resize { dimensions } => Ok(*dimensions),
+      VectorTransform::Center { mean } => expect(mean.len()).map(|_| input),
+      VectorTransform::Project { matrix } => {
+        if let Some(row) = matrix.iter().find(|row| row.len() != input as usize) {
+          expect(row.len())?;
+        }
+        u16::try_from(matrix.len())
+          .map_err(|_| TransformError::TooManyDimensions(matrix.len()))
+      }
+    }
+  }
+
+  pub fn apply(&self, mut vector: Vec<f32>) -> Vec<f32> {
+    match self {
+      VectorTransform::Normalize => {
+        let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if norm > 0f32 {
+          vector.iter_mut().for_each(|value| *value /= norm);
+        }
+        vector
+      },
+      VectorTransform::Resize { dimensions } => {
+        vector.resize(*dimensions as usize, 0f32);
+        vector
+      },
+      VectorTransform::Center { mean } => {
+        vector.iter_mut().zip(mean).for_each(|(value, mean)| *value -= mean);
+        vector
+      },
+      VectorTransform::Project { matrix } => matrix.iter()
+        .map(|row| row.iter().zip(&vector).map(|(weight, value)| weight * value).sum())
+        .collect()
+    }
+  }
+}
+
+/// The component-wise mean of `vectors`, for centering them with
+/// [`VectorTransform::Center`].
+pub fn mean<'v, I>(vectors: I) -> Vec<f32>
+  where I: IntoIterator<Item = &'v [f32]> {
+  let mut sum = Vec::<f64>::new();
+  let mut n_vectors = 0usize;
+  for vector in vectors {
+    if sum.len() < vector.len() {
+      sum.resize(vector.len(), 0f64);
+    }
+    sum.iter_mut().zip(vector).for_each(|(sum, value)| *sum += *value as f64);
+    n_vectors += 1;
+  }
+  sum.into_iter()
+    .map(|sum| (sum / n_vectors.max(1) as f64) as f32)
+    .collect()
+}
+
+/// Preprocessing steps applied in order, both to the vectors an index is
+/// built from and to query vectors. Keep the pipeline with the index, e.g.
+/// by serializing it next to it, so queries are transformed the same way.
+/// Deserializing checks the steps like [`TransformPipeline::then`] does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedPipeline")]
+pub struct TransformPipeline {
+  input_dimensions: u16,
+  steps: Vec<VectorTransform>
+}
+
+/// A deserialized pipeline whose steps haven't been checked yet.
+#[derive(Deserialize)]
+struct UncheckedPipeline {
+  input_dimensions: u16,
+  steps: Vec<VectorTransform>
+}
+
+impl TryFrom<UncheckedPipeline> for TransformPipeline {
+  type Error = TransformError;
+
+  fn try_from(pipeline: UncheckedPipeline) -> Result<Self, Self::Error> {
+    TransformPipeline::with_steps(pipeline.input_dimensions, pipeline.steps)
+  }
+}
+
+impl TransformPipeline {
+  /// An empty pipeline for vectors of `input_dimensions`.
+  pub fn new(input_dimensions: u16) -> Self {
+    TransformPipeline { input_dimensions, steps: Vec::new() }
+  }
+
+  /// A pipeline for vectors of `input_dimensions` running `steps` in order,
+  /// checking each accepts the output of the previous ones.
+  pub fn with_steps<I>(input_dimensions: u16, steps: I) -> Result<Self, TransformError>
+    where I: IntoIterator<Item = VectorTransform> {
+    steps.into_iter().try_fold(Self::new(input_dimensions), Self::then)
+  }
+
+  /// Append `step`, checking that it accepts the output of the previous
+  /// steps.
+  pub fn then(mut self, step: VectorTransform) -> Result<Self, TransformError> {
+    step.output_dimensions(self.output_dimensions())?;
+    self.steps.push(step);
+    Ok(self)
+  }
+
+  pub fn input_dimensions(&self) -> u16 {
+    self.input_dimensions
+  }
+
+  pub fn output_dimensions(&self) -> u16 {
+    self.steps.iter().fold(self.input_dimensions, |dimensions, step| {
+      step.output_dimensions(dimensions).expect("steps are checked when added or deserialized")
+    })
+  }
+
+  pub fn steps(&self) -> &[VectorTransform] {
+    &self.steps
+  }
+
+  /// Whether the pipeline leaves vectors as they are.
+  pub fn is_empty(&self) -> bool {
+    self.steps.is_empty()
+  }
+
+  pub fn apply(&self, vector: Vec<f32>) -> Vec<f32> {
+    self.steps.iter().fold(vector, |vector, step| step.apply(vector))
+  }
+
+  /// Transform a query vector, checking it has the input dimensions.
+  pub fn apply_query(&self, vector: &[f32]) -> Result<Vec<f32>, RecommendError> {
+    if vector.len() != self.input_dimensions as usize {
+      return Err(RecommendError::DimensionMismatch {
+        expected: self.input_dimensions as usize,
+        received: vector.len()
+      })
+    }
+    Ok(self.apply(vector.to_vec()))
+  }
+
+  /// Transform the vectors of `provider` as they are consumed.
+  pub fn wrap<K, P>(&self, provider: P) -> Result<TransformedProvider<P>, TransformError>
+    where P: VectorProvider<K> {
+    if provider.vector_dimensions() != self.input_dimensions {
+      return Err(TransformError::ProviderMismatch {
+        expected: self.input_dimensions,
+        received: provider.vector_dimensions()
+      })
+    }
+    Ok(TransformedProvider { provider, pipeline: self.clone() })
+  }
+}
+
+/// A provider whose vectors are passed through a [`TransformPipeline`].
+/// It's a provider itself, so adapters can be stacked.
+pub struct TransformedProvider<P> {
+  provider: P,
+  pipeline: TransformPipeline
+}
+
+impl<P> TransformedProvider<P> {
+  pub fn pipeline(&self) -> &TransformPipeline {
+    &self.pipeline
+  }
+}
+
+impl<K, P> Iterator for TransformedProvider<P>
+  where P: Iterator<Item = KeyedVector<K>> {
+  type Item = KeyedVector<K>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.provider.next()
+      .map(|KeyedVector { key, vector }| KeyedVector::new(key, self.pipeline.apply(vector)))
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.provider.size_hint()
+  }
+}
+
+impl<K, P> VectorProvider<K> for TransformedProvider<P>
+  where P: VectorProvider<K> {
+  fn vector_dimensions(&self) -> u16 {
+    self.pipeline.output_dimensions()
+  }
+}
+
+/// A recommender built from transformed vectors, which applies the same
+/// transform to query vectors. Item queries are passed through unchanged,
+/// since the stored vectors are already transformed.
+pub struct TransformingRecommender<R> {
+  pipeline: TransformPipeline,
+  recommender: R
+}
+
+impl<R> TransformingRecommender<R> {
+  pub fn new(pipeline: TransformPipeline, recommender: R) -> Self {
+    TransformingRecommender { pipeline, recommender }
+  }
+
+  pub fn pipeline(&self) -> &TransformPipeline {
+    &self.pipeline
+  }
+}
+
+impl<R, K, Rec> Recommender<K, Rec> for TransformingRecommender<R>
+  where R: Recommender<K, Rec> {
+  fn recommend(&self, item_id: &K, n_items: u16)
+        -> Result<RecommendationList<Rec>, RecommendError> {
+    self.recommender.recommend(item_id, n_items)
+  }
+
+  fn recommend_batch(&self, item_ids: &[K], n_items: u16)
+        -> Vec<Result<RecommendationList<Rec>, RecommendError>> {
+    self.recommender.recommend_batch(item_ids, n_items)
+  }
+
+  fn recommend_filtered(&self, item_id: &K, n_items: u16, filter: &CandidateFilter<Rec>)
+        -> Result<RecommendationList<Rec>, RecommendError>
+      where Rec: Eq + Hash {
+    self.recommender.recommend_filtered(item_id, n_items, filter)
+  }
+
+  fn recommend_with(&self, request: &RecommendRequest<K, Rec>)
+        -> Result<RecommendationList<Rec>, RecommendError>
+      where Rec: Eq + Hash {
+    let Subject::Vector(vector) = request.subject else {
+      return self.recommender.recommend_with(request)
+    };
+    let transformed = self.pipeline.apply_query(vector)?;
+    self.recommender.recommend_with(&RecommendRequest {
+      subject: Subject::Vector(&transformed),
+      n_items: request.n_items,
+      offset: request.offset,
+      filter: request.filter,
+      exclude: request.exclude,
+      min_score: request.min_score,
+      search: request.search
+    })
+  }
+}
+
+impl<R, Rec> VectorRecommender<Rec> for TransformingRecommender<R>
+  where R: VectorRecommender<Rec> {
+  fn recommend_by_vector(&self, vector: &[f32], n_items: u16)
+        -> Result<RecommendationList<Rec>, RecommendError> {
+    let transformed = self.pipeline.apply_query(vector)?;
+    self.recommender.recommend_by_vector(&transformed, n_items)
+  }
+
+  fn recommend_by_vector_filtered(&self, vector: &[f32], n_items: u16, filter: &CandidateFilter<Rec>)
+        -> Result<RecommendationList<Rec>, RecommendError>
+      where Rec: Eq + Hash {
+    let transformed = self.pipeline.apply_query(vector)?;
+    self.recommender.recommend_by_vector_filtered(&transformed, n_items, filter)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Recommendation;
+
+  /// Recommends one item per component of the query vector, scored by the
+  /// component, so tests can see the vector the backend received.
+  struct Echo;
+
+  impl VectorRecommender<usize> for Echo {
+    fn recommend_by_vector(&self, vector: &[f32], _n_items: u16)
+          -> Result<RecommendationList<usize>, RecommendError> {
+      Ok(RecommendationList(vector.iter()
+        .enumerate()
+        .map(|(i, value)| Recommendation::new(i, *value))
+        .collect()))
+    }
+  }
+
+  impl Recommender<usize, usize> for Echo {
+    fn recommend(&self, item_id: &usize, _n_items: u16)
+          -> Result<RecommendationList<usize>, RecommendError> {
+      Ok(RecommendationList(vec![Recommendation::new(*item_id, 1f32)]))
+    }
+
+    fn recommend_with(&self, request: &RecommendRequest<usize, usize>)
+          -> Result<RecommendationList<usize>, RecommendError> {
+      match request.subject {
+        Subject::Item(item_id) => self.recommend(item_id, request.n_items),
+        Subject::Vector(vector) => self.recommend_by_vector(vector, request.n_items)
+      }
+    }
+  }
+
+  /// Two dimensional vectors.
+  struct Vectors(std::vec::IntoIter<KeyedVector<u32>>);
+
+  impl Iterator for Vectors {
+    type Item = KeyedVector<u32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+      self.0.next()
+    }
+  }
+
+  impl VectorProvider<u32> for Vectors {
+    fn vector_dimensions(&self) -> u16 {
+      2
+    }
+  }
+
+  fn scores(recs: Result<RecommendationList<usize>, RecommendError>) -> Vec<f32> {
+    recs.unwrap().0.iter().map(|rec| rec.score).collect()
+  }
+
+  fn pipeline() -> TransformPipeline {
+    TransformPipeline::with_steps(2, [
+      VectorTransform::Center { mean: vec![1.0, 1.0] },
+      VectorTransform::Project { matrix: vec![vec![1.0, 0.0], vec![0.0, 2.0], vec![1.0, 1.0]] },
+      VectorTransform::Normalize
+    ]).unwrap()
+  }
+
+  #[test]
+  fn steps_transform_vectors() {
+    assert_eq!(VectorTransform::Normalize.apply(vec![3.0, 4.0]), vec![0.6, 0.8]);
+    assert_eq!(VectorTransform::Normalize.apply(vec![0.0, 0.0]), vec![0.0, 0.0]);
+    assert_eq!(VectorTransform::Resize { dimensions: 1 }.apply(vec![3.0, 4.0]), vec![3.0]);
+    assert_eq!(VectorTransform::Resize { dimensions: 3 }.apply(vec![3.0, 4.0]), vec![3.0, 4.0, 0.0]);
+    assert_eq!(VectorTransform::Center { mean: vec![1.0, 2.0] }.apply(vec![3.0, 4.0]), vec![2.0, 2.0]);
+    let project = VectorTransform::Project { matrix: vec![vec![1.0, 1.0], vec![2.0, -1.0], vec![0.0, 0.5]] };
+    assert_eq!(project.apply(vec![3.0, 4.0]), vec![7.0, 2.0, 2.0]);
+  }
+
+  #[test]
+  fn mean_averages_components() {
+    let vectors = [vec![1.0, 2.0], vec![3.0, 6.0]];
+    assert_eq!(mean(vectors.iter().map(Vec::as_slice)), vec![2.0, 4.0]);
+    assert!(mean(std::iter::empty()).is_empty());
+  }
+
+  #[test]
+  fn steps_are_checked_against_their_input() {
+    let pipeline = pipeline();
+    assert_eq!((pipeline.input_dimensions(), pipeline.output_dimensions()), (2, 3));
+    // (2, 3) centers to (1, 2), projects to (1, 4, 3) and has norm √26
+    let norm = 26f32.sqrt();
+    assert_eq!(pipeline.apply(vec![2.0, 3.0]), vec![1.0 / norm, 4.0 / norm, 3.0 / norm]);
+    let error = pipeline.then(VectorTransform::Center { mean: vec![0.0; 2] }).unwrap_err();
+    assert!(matches!(error, TransformError::DimensionMismatch { step: "center", expected: 2, received: 3 }));
+    let error = TransformPipeline::new(2)
+      .then(VectorTransform::Project { matrix: vec![vec![1.0, 0.0], vec![1.0]] })
+      .unwrap_err();
+    assert!(matches!(error, TransformError::DimensionMismatch { step: "project", expected: 1, received: 2 }));
+  }
+
+  #[test]
+  fn deserializing_checks_the_steps() {
+    let json = serde_json::to_string(&pipeline()).unwrap();
+    assert_eq!(serde_json::from_str::<TransformPipeline>(&json).unwrap(), pipeline());
+    let json = r#"{"input_dimensions": 3, "steps": [{"kind": "center", "mean": [0, 0]}]}"#;
+    let error = serde_json::from_str::<TransformPipeline>(json).unwrap_err();
+    assert!(error.to_string().contains("center expects 2 dimensions, receives 3"));
+  }
+
+  #[test]
+  fn wrapped_providers_are_transformed() {
+    let vectors = Vectors(vec![KeyedVector::new(1, vec![2.0, 3.0])].into_iter());
+    let provider = pipeline().wrap(vectors).unwrap();
+    assert_eq!(provider.vector_dimensions(), 3);
+    assert_eq!(provider.map(|vector| vector.vector.len()).collect::<Vec<_>>(), vec![3]);
+    let vectors = Vectors(Vec::new().into_iter());
+    let error = TransformPipeline::new(3).wrap::<u32, _>(vectors).err().unwrap();
+    assert!(matches!(error, TransformError::ProviderMismatch { expected: 3, received: 2 }));
+  }
+
+  #[test]
+  fn query_vectors_are_transformed() {
+    let recommender = TransformingRecommender::new(pipeline(), Echo);
+    let norm = 26f32.sqrt();
+    let expected = vec![1.0 / norm, 4.0 / norm, 3.0 / norm];
+    assert_eq!(scores(recommender.recommend_by_vector(&[2.0, 3.0], 3)), expected);
+    let request = RecommendRequest::vector(&[2.0, 3.0], 3);
+    assert_eq!(scores(recommender.recommend_with(&request)), expected);
+    // stored items are already transformed
+    assert_eq!(scores(recommender.recommend_with(&RecommendRequest::item(&7, 3))), vec![1.0]);
+    assert!(matches!(
+      recommender.recommend_by_vector(&[1.0], 3),
+      Err(RecommendError::DimensionMismatch { expected: 2, received: 1 })
+    ));
+  }
+}